@@ -0,0 +1,5 @@
+pub mod render;
+pub mod router;
+
+#[cfg(test)]
+mod tests;
@@ -1,7 +1 @@
-#[allow(dead_code)]
-pub mod router;
-
-#[cfg(test)]
-mod tests;
-
 pub fn main() {}
@@ -0,0 +1,357 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub enum Render {
+    Plain(String),
+    File(String, String),
+    Mime(String, String),
+    Json(String),
+    Stream(String, Box<dyn Iterator<Item = Vec<u8>> + Send>),
+    Status {
+        code: u16,
+        body: String,
+        content_type: String,
+    },
+    Redirect {
+        location: String,
+        permanent: bool,
+    },
+    Bytes {
+        data: Vec<u8>,
+        content_type: String,
+    },
+    // extra response headers on top of the wrapped render's own
+    Headers(Vec<(String, String)>, Box<Render>),
+}
+
+pub fn reason_phrase(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+// the content type implied by a path's extension, falling back to octet-stream
+pub fn mime_for_path(path: &str) -> &'static str {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match ext.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+pub(crate) fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// drops CR and LF so a header built from request data can't smuggle in further headers
+fn strip_crlf(value: &str) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+impl Render {
+    // panics if `code` isn't a valid HTTP status (100-599)
+    pub fn status(code: u16, body: &str, content_type: &str) -> Render {
+        assert!(
+            (100..=599).contains(&code),
+            "invalid HTTP status code {}",
+            code
+        );
+        Render::Status {
+            code,
+            body: body.to_string(),
+            content_type: content_type.to_string(),
+        }
+    }
+
+    pub fn not_found() -> Render {
+        Render::status(404, reason_phrase(404), "text/plain; charset=utf-8")
+    }
+
+    pub fn created(body: &str) -> Render {
+        Render::status(201, body, "text/plain; charset=utf-8")
+    }
+
+    pub fn server_error() -> Render {
+        Render::status(500, reason_phrase(500), "text/plain; charset=utf-8")
+    }
+
+    pub fn json_error(code: u16, message: &str) -> Render {
+        Render::status(
+            code,
+            &format!("{{\"error\":{}}}", escape_json(message)),
+            "application/json",
+        )
+    }
+
+    // a temporary (302) redirect to `location`
+    pub fn redirect(location: &str) -> Render {
+        Render::Redirect {
+            location: location.to_string(),
+            permanent: false,
+        }
+    }
+
+    // a permanent (301) redirect to `location`
+    pub fn permanent_redirect(location: &str) -> Render {
+        Render::Redirect {
+            location: location.to_string(),
+            permanent: true,
+        }
+    }
+
+    // adds a response header, replacing any earlier value set under the same name
+    pub fn with_header(self, name: &str, value: &str) -> Render {
+        let (mut headers, inner) = match self {
+            Render::Headers(headers, inner) => (headers, inner),
+            render => (Vec::new(), Box::new(render)),
+        };
+        headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        headers.push((name.to_string(), value.to_string()));
+        Render::Headers(headers, inner)
+    }
+
+    // adds each of `defaults` whose name isn't already set, so headers set earlier win
+    pub fn with_default_headers(self, defaults: &[(String, String)]) -> Render {
+        let mut render = self;
+        for (name, value) in defaults.iter() {
+            if render.header(name).is_none() {
+                render = render.with_header(name, value);
+            }
+        }
+        render
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        match self {
+            Render::Headers(headers, _) => headers,
+            _ => &[],
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .iter()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Render::Headers(_, inner) => inner.status_code(),
+            Render::Status { code, .. } => *code,
+            Render::Redirect {
+                permanent: true, ..
+            } => 301,
+            Render::Redirect { .. } => 302,
+            _ => 200,
+        }
+    }
+
+    // a file response whose content type is inferred from its extension; use
+    // `Render::File` directly to override it
+    pub fn file(path: &str) -> Render {
+        Render::File(path.to_string(), mime_for_path(path).to_string())
+    }
+
+    pub fn bytes(data: &[u8], content_type: &str) -> Render {
+        Render::Bytes {
+            data: data.to_vec(),
+            content_type: content_type.to_string(),
+        }
+    }
+
+    pub fn plain_with_type(body: &str, content_type: &str) -> Render {
+        Render::Mime(content_type.to_string(), body.to_string())
+    }
+
+    pub fn content_type(&self) -> String {
+        match self {
+            Render::Plain(_) => "text/plain; charset=utf-8".to_string(),
+            Render::File(_, content_type) => content_type.clone(),
+            Render::Mime(content_type, _) => content_type.clone(),
+            Render::Json(_) => "application/json".to_string(),
+            Render::Stream(content_type, _) => content_type.clone(),
+            Render::Status { content_type, .. } => content_type.clone(),
+            Render::Redirect { .. } => "text/plain; charset=utf-8".to_string(),
+            Render::Bytes { content_type, .. } => content_type.clone(),
+            Render::Headers(_, inner) => inner.content_type(),
+        }
+    }
+
+    // the size of the body in bytes, or `None` when it isn't known up front (streams, or
+    // files that can't be read)
+    pub fn body_len(&self) -> Option<usize> {
+        match self {
+            Render::Plain(body)
+            | Render::Mime(_, body)
+            | Render::Json(body)
+            | Render::Status { body, .. } => Some(body.len()),
+            Render::File(path, _) => fs::metadata(path).ok().map(|meta| meta.len() as usize),
+            Render::Stream(_, _) => None,
+            Render::Redirect { .. } => Some(0),
+            Render::Bytes { data, .. } => Some(data.len()),
+            Render::Headers(_, inner) => inner.body_len(),
+        }
+    }
+
+    pub fn write_body<W: Write>(self, out: &mut W) -> io::Result<()> {
+        match self {
+            Render::Plain(body)
+            | Render::Mime(_, body)
+            | Render::Json(body)
+            | Render::Status { body, .. } => out.write_all(body.as_bytes()),
+            Render::File(path, _) => out.write_all(&fs::read(path)?),
+            Render::Stream(_, chunks) => {
+                for chunk in chunks {
+                    out.write_all(&chunk)?;
+                }
+                Ok(())
+            }
+            Render::Redirect { .. } => Ok(()),
+            Render::Bytes { data, .. } => out.write_all(&data),
+            Render::Headers(_, inner) => inner.write_body(out),
+        }
+    }
+
+    // the status line, Content-Type and any extra headers, up to but not including the length
+    fn write_preamble<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let code = self.status_code();
+        write!(out, "HTTP/1.1 {} {}\r\n", code, reason_phrase(code))?;
+        write!(
+            out,
+            "Content-Type: {}\r\n",
+            strip_crlf(&self.content_type())
+        )?;
+        for (name, value) in self.headers().iter() {
+            write!(out, "{}: {}\r\n", strip_crlf(name), strip_crlf(value))?;
+        }
+        if let Render::Redirect { location, .. } = self.inner() {
+            write!(out, "Location: {}\r\n", strip_crlf(location))?;
+        }
+        Ok(())
+    }
+
+    fn inner(&self) -> &Render {
+        match self {
+            Render::Headers(_, inner) => inner,
+            render => render,
+        }
+    }
+
+    fn into_inner(self) -> Render {
+        match self {
+            Render::Headers(_, inner) => *inner,
+            render => render,
+        }
+    }
+
+    // the body of anything but a stream, without consuming the render
+    fn body_bytes(&self) -> io::Result<Vec<u8>> {
+        Ok(match self.inner() {
+            Render::Plain(body)
+            | Render::Mime(_, body)
+            | Render::Json(body)
+            | Render::Status { body, .. } => body.as_bytes().to_vec(),
+            Render::File(path, _) => fs::read(path)?,
+            Render::Bytes { data, .. } => data.clone(),
+            Render::Redirect { .. } | Render::Stream(_, _) | Render::Headers(_, _) => Vec::new(),
+        })
+    }
+
+    // the full HTTP/1.1 response as wire bytes. A file that can't be read becomes a 500.
+    // Streams can only be drained by `write_http`, so here they get an empty chunked body.
+    pub fn to_http_response(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let Render::Stream(_, _) = self.inner() {
+            self.write_preamble(&mut out).unwrap();
+            out.extend_from_slice(b"Transfer-Encoding: chunked\r\n\r\n0\r\n\r\n");
+            return out;
+        }
+        let body = match self.body_bytes() {
+            Ok(body) => body,
+            Err(_) => return Render::server_error().to_http_response(),
+        };
+        self.write_preamble(&mut out).unwrap();
+        out.extend_from_slice(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+        out.extend_from_slice(&body);
+        out
+    }
+
+    pub fn write_http<W: Write>(self, out: &mut W) -> io::Result<()> {
+        self.write_preamble(out)?;
+        match self.into_inner() {
+            Render::Stream(_, chunks) => {
+                write!(out, "Transfer-Encoding: chunked\r\n\r\n")?;
+                for chunk in chunks.filter(|chunk| !chunk.is_empty()) {
+                    write!(out, "{:X}\r\n", chunk.len())?;
+                    out.write_all(&chunk)?;
+                    write!(out, "\r\n")?;
+                }
+                write!(out, "0\r\n\r\n")
+            }
+            render => {
+                let mut body = Vec::new();
+                render.write_body(&mut body)?;
+                write!(out, "Content-Length: {}\r\n\r\n", body.len())?;
+                out.write_all(&body)
+            }
+        }
+    }
+
+    // the response `write_http` would produce minus the body, as sent for a HEAD request.
+    // Streams are left unconsumed, so their length is unknown and no Content-Length is sent.
+    pub fn write_http_head<W: Write>(self, out: &mut W) -> io::Result<()> {
+        self.write_preamble(out)?;
+        if let Some(len) = self.body_len() {
+            write!(out, "Content-Length: {}\r\n", len)?;
+        }
+        write!(out, "\r\n")
+    }
+}
@@ -1,17 +1,50 @@
+use crate::render::escape_json;
+pub use crate::render::Render;
 use lazy_static::lazy_static;
 use regex::Regex;
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::*;
+use std::fmt;
 use std::hash::*;
+use std::io::{self, Write};
+#[cfg(feature = "server")]
+use std::io::{BufRead, BufReader, Read};
+use std::marker::PhantomData;
+#[cfg(feature = "server")]
+use std::net::{TcpListener, TcpStream};
 use std::ops::Index;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
 
 lazy_static! {
-    static ref PATH_REG: Regex = Regex::new(r"\A(/[^;#:\s/]+|/[:#;][^;#:\s/]+)*/?\z").unwrap();
+    static ref PATH_REG: Regex = {
+        let class = |trailing: Option<bool>| {
+            SIGILS
+                .iter()
+                .filter(|sigil| trailing.map_or(true, |trailing| sigil.trailing == trailing))
+                .map(|sigil| regex::escape(&sigil.sigil.to_string()))
+                .collect::<String>()
+        };
+        let (all, single, trailing) = (class(None), class(Some(false)), class(Some(true)));
+        Regex::new(&format!(
+            r"\A(/[^{all}\s/][^;#:\s/]*|/[{single}][^{all}\s/<]+|/#[^{all}\s/<]+<[^/\s]+>)*(/[{trailing}][^{all}\s/]+)?/?\z",
+            all = all,
+            single = single,
+            trailing = trailing
+        ))
+        .unwrap()
+    };
 }
 lazy_static! {
-    static ref DOM_REG_SIMPLE: Regex = Regex::new(r"\A([^\.\*\s]+\.[^\.\s]+)+\z").unwrap();
+    // constraint regexes by pattern, so each is compiled once however many routes use it
+    static ref CONSTRAINTS: Mutex<HashMap<String, Regex>> = Mutex::new(HashMap::new());
 }
 lazy_static! {
-    static ref DOM_REG_WILDCARD: Regex = Regex::new(r"\A\*\.([^\.\*\s]+\.[^\.\s]+)+\z").unwrap();
+    static ref DOM_REGEXES: DomainRegexes = DomainRegexes::new();
 }
 lazy_static! {
     static ref VAR_INT: Regex = Regex::new(r"\A\-?[0-9]*\z").unwrap();
@@ -19,27 +52,109 @@ lazy_static! {
 lazy_static! {
     static ref VAR_FLOAT: Regex = Regex::new(r"\A\-?[0-9]*\.[0-9]*\z").unwrap();
 }
+lazy_static! {
+    static ref QUERY_SPEC_REG: Regex =
+        Regex::new(r"\A([^=&:!\s]+)=:(int|float|string|bool)(?:=([^&\s]*))?\z").unwrap();
+}
+lazy_static! {
+    static ref QUERY_ABSENT_REG: Regex = Regex::new(r"\A!([^=&:!\s]+)\z").unwrap();
+}
 lazy_static! {
     static ref VAR_STRING: Regex = Regex::new(r"\A[^/\s]*\z").unwrap();
 }
 
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum RouteError {
+    InvalidPathFormat,
+    WildcardNotLast,
+    InvalidDomain,
+    ParamParseFailure,
+    DuplicateRoute,
+    FallbackShadowed,
+    InvalidQuerySpec,
+    UnknownHandler,
+    UnknownRoute,
+    IncompatibleAlias,
+    ReservedParam,
+    InvalidConstraint,
+    AmbiguousRoute,
+    DynamicPrefix,
+}
+
+impl RouteError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            RouteError::InvalidPathFormat => "invalid route format!",
+            RouteError::WildcardNotLast => "wildcard must be the last segment",
+            RouteError::InvalidDomain => "invalid domain!",
+            RouteError::ParamParseFailure => "could not parse url param!",
+            RouteError::DuplicateRoute => "a route identical to this one has already been defined!",
+            RouteError::FallbackShadowed => {
+                "a catch-all route at the root would shadow the fallback!"
+            }
+            RouteError::InvalidQuerySpec => "invalid query spec!",
+            RouteError::UnknownHandler => "no handler with this name has been registered!",
+            RouteError::UnknownRoute => "no route matching this path has been defined!",
+            RouteError::IncompatibleAlias => "alias must capture the same vars as its target!",
+            RouteError::ReservedParam => "`__path` is reserved for the injected request path!",
+            RouteError::InvalidConstraint => "invalid route constraint regex",
+            RouteError::AmbiguousRoute => "ambiguous route overlaps an existing route",
+            RouteError::DynamicPrefix => "prefix may only contain literal segments!",
+        }
+    }
+
+    // malformed requests map to client errors; anything else signals a problem with how the
+    // router itself was configured
+    pub fn status_code(&self) -> u16 {
+        match self {
+            RouteError::InvalidPathFormat
+            | RouteError::WildcardNotLast
+            | RouteError::InvalidDomain
+            | RouteError::ParamParseFailure => 400,
+            RouteError::UnknownRoute => 404,
+            RouteError::DuplicateRoute
+            | RouteError::FallbackShadowed
+            | RouteError::InvalidQuerySpec
+            | RouteError::UnknownHandler
+            | RouteError::IncompatibleAlias
+            | RouteError::ReservedParam
+            | RouteError::InvalidConstraint
+            | RouteError::AmbiguousRoute
+            | RouteError::DynamicPrefix => 500,
+        }
+    }
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum VerbParam {
     String(String),
     Int(i64),
     Float(f64),
     Bool(bool),
+    Array(Vec<VerbParam>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct VerbParams {
-    hashmap: HashMap<String, VerbParam>,
+    hashmap: HashMap<String, Vec<VerbParam>>,
 }
 
 impl Index<&'static str> for VerbParams {
     type Output = VerbParam;
     fn index(&self, key: &'static str) -> &Self::Output {
-        &self.hashmap[key]
+        &self.hashmap[key][0]
+    }
+}
+
+impl Default for VerbParams {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -50,30 +165,578 @@ impl VerbParams {
         }
     }
 
+    // sets `key` to `value`, replacing every value it had
     pub fn add(&mut self, key: String, value: VerbParam) {
-        self.hashmap.insert(key, value);
+        self.hashmap.insert(key, vec![value]);
+    }
+
+    // appends `value` to those already given for `key`, as for `?tag=a&tag=b`
+    pub fn add_multi(&mut self, key: String, value: VerbParam) {
+        self.hashmap.entry(key).or_default().push(value);
+    }
+
+    // the first value given for `key`
+    pub fn get(&self, key: &str) -> Option<&VerbParam> {
+        self.hashmap.get(key)?.first()
+    }
+
+    pub fn get_all(&self, key: &str) -> &[VerbParam] {
+        self.hashmap
+            .get(key)
+            .map_or(&[], |values| values.as_slice())
+    }
+
+    // one entry per key with its first value, like `get`; see `get_all` for the rest
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &VerbParam)> {
+        self.hashmap
+            .iter()
+            .filter_map(|(key, values)| Some((key.as_str(), values.first()?)))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.hashmap.keys().map(|key| key.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashmap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashmap.is_empty()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.hashmap.contains_key(key)
+    }
+
+    // typed counterparts to `get` that parse string values (as query and form params
+    // arrive), returning `None` when the param is missing or doesn't parse
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        match self.get(key)? {
+            VerbParam::Int(value) => Some(*value),
+            VerbParam::String(value) => value.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, key: &str) -> Option<f64> {
+        match self.get(key)? {
+            VerbParam::Float(value) => Some(*value),
+            VerbParam::Int(value) => Some(*value as f64),
+            VerbParam::String(value) => parse_float(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            VerbParam::Bool(value) => Some(*value),
+            VerbParam::String(value) => parse_bool(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            VerbParam::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    // combines every source into one bag; on key collisions url params win over post
+    // params, which win over get params
+    pub fn merged(url: &UrlParams, get: &VerbParams, post: &VerbParams) -> VerbParams {
+        let mut params = get.clone();
+        for (key, values) in post.hashmap.iter() {
+            params.hashmap.insert(key.clone(), values.clone());
+        }
+        for (key, value) in url.hashmap.iter() {
+            params.add(key.clone(), value.clone().into());
+        }
+        params
+    }
+
+    pub fn from_query(query: &str) -> VerbParams {
+        VerbParams::from_pairs(query, false)
+    }
+
+    // parses an `application/x-www-form-urlencoded` body, which unlike a query string encodes
+    // spaces as `+`
+    pub fn from_form(body: &str) -> VerbParams {
+        VerbParams::from_pairs(body, true)
+    }
+
+    // flattens a top-level JSON object into params. Nested objects and arrays (and nulls) are
+    // kept as their serialized JSON text.
+    #[cfg(feature = "serde_json")]
+    pub fn from_json(body: &str) -> Result<VerbParams, &'static str> {
+        use serde_json::Value;
+        let object = match serde_json::from_str(body) {
+            Ok(Value::Object(object)) => object,
+            Ok(_) => return Err("expected a JSON object"),
+            Err(_) => return Err("invalid JSON"),
+        };
+        let mut params = VerbParams::new();
+        for (key, value) in object {
+            let param = match value {
+                Value::Bool(value) => VerbParam::Bool(value),
+                Value::Number(number) => match number.as_i64() {
+                    Some(value) => VerbParam::Int(value),
+                    None => VerbParam::Float(number.as_f64().unwrap_or(f64::NAN)),
+                },
+                Value::String(value) => VerbParam::String(value),
+                other => VerbParam::String(other.to_string()),
+            };
+            params.add(key, param);
+        }
+        Ok(params)
+    }
+
+    fn from_pairs(input: &str, plus_as_space: bool) -> VerbParams {
+        let decode = |value: &str| {
+            if plus_as_space {
+                percent_decode(&value.replace('+', " ")).into_owned()
+            } else {
+                percent_decode(value).into_owned()
+            }
+        };
+        let mut params = VerbParams::new();
+        for pair in input.split('&') {
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            params.add_multi(decode(key), VerbParam::String(decode(value)));
+        }
+        params
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum QueryType {
+    Int,
+    Float,
+    String,
+    Bool,
+}
+
+impl QueryType {
+    pub fn parse(&self, value: &str) -> Option<VerbParam> {
+        match self {
+            QueryType::Int => value.parse().ok().map(VerbParam::Int),
+            QueryType::Float => value.parse().ok().map(VerbParam::Float),
+            QueryType::String => Some(VerbParam::String(value.to_string())),
+            QueryType::Bool => match value {
+                "true" | "1" => Some(VerbParam::Bool(true)),
+                "false" | "0" => Some(VerbParam::Bool(false)),
+                _ => None,
+            },
+        }
+    }
+}
+
+// a typed query param declared in a route path, e.g. `/list?limit=:int=20`; params without a
+// default are required for the route to match. `!name` instead requires the param to be
+// absent, e.g. `/feed?!format`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueryParam {
+    pub name: String,
+    pub kind: QueryType,
+    pub default: Option<VerbParam>,
+    pub absent: bool,
+}
+
+impl QueryParam {
+    fn parse(spec: &str) -> Result<QueryParam, RouteError> {
+        if let Some(caps) = QUERY_ABSENT_REG.captures(spec) {
+            return Ok(QueryParam {
+                name: caps[1].to_string(),
+                kind: QueryType::String,
+                default: None,
+                absent: true,
+            });
+        }
+        let caps = match QUERY_SPEC_REG.captures(spec) {
+            Some(caps) => caps,
+            None => return Err(RouteError::InvalidQuerySpec),
+        };
+        let kind = match &caps[2] {
+            "int" => QueryType::Int,
+            "float" => QueryType::Float,
+            "bool" => QueryType::Bool,
+            _ => QueryType::String,
+        };
+        let default = match caps.get(3) {
+            Some(default) => match kind.parse(default.as_str()) {
+                Some(value) => Some(value),
+                None => return Err(RouteError::InvalidQuerySpec),
+            },
+            None => None,
+        };
+        Ok(QueryParam {
+            name: caps[1].to_string(),
+            kind,
+            default,
+            absent: false,
+        })
+    }
+}
+
+impl fmt::Display for QueryParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.absent {
+            return write!(f, "!{}", self.name);
+        }
+        let kind = match self.kind {
+            QueryType::Int => "int",
+            QueryType::Float => "float",
+            QueryType::String => "string",
+            QueryType::Bool => "bool",
+        };
+        write!(f, "{}=:{}", self.name, kind)?;
+        match &self.default {
+            Some(VerbParam::String(value)) => write!(f, "={}", value),
+            Some(VerbParam::Int(value)) => write!(f, "={}", value),
+            Some(VerbParam::Float(value)) => write!(f, "={}", value),
+            Some(VerbParam::Bool(value)) => write!(f, "={}", value),
+            _ => Ok(()),
+        }
+    }
+}
+
+// the url param `Router::set_inject_full_path` stores the request path under
+pub const FULL_PATH_PARAM: &str = "__path";
+
+// the url param catch-alls store the extension of the captured path's last component
+// under (e.g. `png` for `img/logo.png`), unless the route declares a var of that name
+pub const EXT_PARAM: &str = "ext";
+
+// how long `Router::serve` waits on a silent client before dropping the connection
+#[cfg(feature = "server")]
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// the most `Router::serve` reads of a request line plus headers before answering 431
+#[cfg(feature = "server")]
+const MAX_HEAD: usize = 16 * 1024;
+
+// the body limit `Router::serve` applies when neither the route nor the router sets one
+#[cfg(feature = "server")]
+pub const DEFAULT_MAX_BODY: usize = 1024 * 1024;
+
+// reads one line of a request head, or `None` once the head has used up `budget` bytes. An
+// empty line is returned at the end of the stream.
+#[cfg(feature = "server")]
+fn read_head_line<R: BufRead>(reader: &mut R, budget: &mut usize) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let read = reader.take(*budget as u64 + 1).read_line(&mut line)?;
+    if read > *budget {
+        return Ok(None);
+    }
+    *budget -= read;
+    Ok(Some(line))
+}
+
+// splits a full url into its authority, if any, and its path and query. Bare paths are
+// passed through with no authority.
+fn split_url(url: &str) -> (Option<&str>, &str) {
+    let (authority, rest) = match url.split_once("://") {
+        Some((_, rest)) => match rest.find('/') {
+            Some(i) => (Some(&rest[..i]), &rest[i..]),
+            None => (Some(rest), "/"),
+        },
+        None => (None, url),
+    };
+    (authority.filter(|authority| !authority.is_empty()), rest)
+}
+
+// the host of an authority, without any port
+fn host_name(authority: &str) -> &str {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => authority,
+    }
+}
+
+// the parts of a mount or group prefix, which may only contain literal segments
+fn static_prefix(prefix: &str) -> Result<Vec<RoutePart>, RouteError> {
+    if !prefix.starts_with('/') || !PATH_REG.is_match(prefix) {
+        return Err(RouteError::InvalidPathFormat);
+    }
+    let mut parts = Vec::new();
+    for token in prefix.split('/').filter(|token| !token.is_empty()) {
+        if token.starts_with(is_sigil) {
+            return Err(RouteError::DynamicPrefix);
+        }
+        parts.push(RoutePart::Path(percent_decode(token).into_owned()));
+    }
+    Ok(parts)
+}
+
+fn file_ext(path: &str) -> Option<&str> {
+    let file = path.rsplit('/').next()?;
+    match file.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => Some(ext),
+        _ => None,
+    }
+}
+
+// decodes `%XX` escapes, leaving malformed ones as they are. Used on query and form params
+// and on each path segment once the path has been split, so an encoded `%2F` stays part of
+// its segment rather than separating two.
+pub fn percent_decode(value: &str) -> Cow<'_, str> {
+    if !value.contains('%') {
+        return Cow::Borrowed(value);
+    }
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match (bytes[i], value.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                u8::from_str_radix(hex, 16).ok()
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+// the inverse of `percent_decode` for a single path segment: everything but unreserved
+// characters is escaped
+pub fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn split_query(path: &str) -> (&str, &str) {
+    path.split_once('?').unwrap_or((path, ""))
+}
+
+// splits a route definition into its path, whether its final var is optional, and its query
+// spec. An optional var is marked by a trailing `?`, doubled when a query spec follows, as in
+// `/archive/:year/:month?` or `/archive/:year/:month??limit=:int`. A `?` inside a `<...>`
+// constraint is part of the constraint.
+fn split_route_path(path: &str) -> (&str, bool, &str) {
+    let mut depth = 0;
+    for (i, c) in path.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' if depth > 0 => depth -= 1,
+            '?' if depth == 0 => {
+                let rest = &path[i + 1..];
+                return match rest.strip_prefix('?') {
+                    Some(query) => (&path[..i], true, query),
+                    None if rest.is_empty() => (&path[..i], true, ""),
+                    None => (&path[..i], false, rest),
+                };
+            }
+            _ => {}
+        }
     }
+    (path, false, "")
 }
 
-pub enum Render {
-    Plain(String),
-    File(String, String),
-    Mime(String, String),
-    Json(String),
+// the query constraints of a route in a canonical order, so routes differing only in their
+// query specs get distinct keys
+fn query_key(query: &[QueryParam]) -> Vec<String> {
+    let mut specs: Vec<String> = query.iter().map(|param| param.to_string()).collect();
+    specs.sort_unstable();
+    specs
 }
 
 pub type Endpoint = fn(&UrlParams, &VerbParams, &VerbParams) -> Render;
 
+pub type Selector = fn(&UrlParams, &VerbParams, &VerbParams) -> Endpoint;
+
+pub trait HandlerError {
+    fn status(&self) -> u16 {
+        500
+    }
+
+    fn message(&self) -> String;
+}
+
+// runs before the endpoint of every route in its scope; returning a render short-circuits
+// the request with it, `None` lets the request continue
+pub type Middleware = fn(&UrlParams, &VerbParams, &VerbParams) -> Option<Render>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Scope {
+    // routes whose path starts with the given segments, e.g. `/admin`
+    Prefix(&'static str),
+    // routes registered with `RouteBuilder::tag`
+    Tag(&'static str),
+    // routes registered under the given domain
+    Domain(&'static str),
+}
+
+impl Scope {
+    fn matches(&self, route: &Route) -> bool {
+        match self {
+            Scope::Prefix(prefix) => {
+                let prefix = prefix.trim_end_matches('/');
+                let path = route.to_path_string();
+                match path.strip_prefix(prefix) {
+                    Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                    None => false,
+                }
+            }
+            Scope::Tag(tag) => route.tags.iter().any(|t| t == tag),
+            Scope::Domain(dom) => route
+                .domains()
+                .iter()
+                .any(|route_dom| route_dom.eq_ignore_ascii_case(dom)),
+        }
+    }
+}
+
+// the outcome of `Router::find_or_405`: a path registered under other verbs only is a 405
+// rather than a 404
+#[derive(Debug, Clone)]
+pub enum FindResult {
+    Found(Endpoint),
+    MethodNotAllowed(Vec<Verb>),
+    NotFound,
+}
+
+pub type FallibleEndpoint =
+    fn(&UrlParams, &VerbParams, &VerbParams) -> Result<Render, Box<dyn HandlerError>>;
+
+// stand-in target for routes registered via `route_fallible`. A plain `Endpoint` can't carry
+// the handler, so these routes are served by `dispatch` and `RouteMatch::call` instead.
+fn fallible_target(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::server_error()
+}
+
+// the response for a handler error; statuses outside 100-599 become a 500
+fn error_render(err: &dyn HandlerError, json: bool) -> Render {
+    let code = match err.status() {
+        code @ 100..=599 => code,
+        _ => 500,
+    };
+    if json {
+        Render::json_error(code, &err.message())
+    } else {
+        Render::status(code, &err.message(), "text/plain; charset=utf-8")
+    }
+}
+
+// stand-in target for routes registered via `route_dynamic`, whose endpoint is only known
+// once the selector has seen the request. Lookups resolve it through the selector, so it
+// is never handed out.
+fn unselected(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("404".to_string())
+}
+
+// a calendar date captured by an `@name` segment
+#[derive(Debug, PartialEq, Clone, Copy, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Date> {
+        let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+        let days = match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if leap => 29,
+            2 => 28,
+            _ => return None,
+        };
+        if day == 0 || day > days {
+            return None;
+        }
+        Some(Date { year, month, day })
+    }
+
+    // parses `YYYY-MM-DD`
+    pub fn parse(value: &str) -> Option<Date> {
+        let mut fields = value.split('-');
+        let date = Date::from_fields(fields.next()?, fields.next()?, fields.next()?)?;
+        match fields.next() {
+            Some(_) => None,
+            None => Some(date),
+        }
+    }
+
+    fn from_fields(year: &str, month: &str, day: &str) -> Option<Date> {
+        let digits = |field: &str, len: usize| {
+            field.len() == len && field.bytes().all(|b| b.is_ascii_digit())
+        };
+        if !digits(year, 4) || !digits(month, 2) || !digits(day, 2) {
+            return None;
+        }
+        Date::new(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum UrlParam {
     String(String),
     Int(i64),
     Float(f64),
+    Bool(bool),
+    Date(Date),
+    Bytes(Vec<u8>),
+    #[cfg(feature = "uuid")]
+    Uuid(uuid::Uuid),
+    Array(Vec<UrlParam>),
+}
+
+impl From<UrlParam> for VerbParam {
+    fn from(param: UrlParam) -> Self {
+        match param {
+            UrlParam::String(value) => VerbParam::String(value),
+            UrlParam::Int(value) => VerbParam::Int(value),
+            UrlParam::Float(value) => VerbParam::Float(value),
+            UrlParam::Bool(value) => VerbParam::Bool(value),
+            UrlParam::Date(date) => VerbParam::String(date.to_string()),
+            #[cfg(feature = "uuid")]
+            UrlParam::Uuid(uuid) => VerbParam::String(uuid.to_string()),
+            UrlParam::Bytes(bytes) => VerbParam::Array(
+                bytes
+                    .into_iter()
+                    .map(|b| VerbParam::Int(b as i64))
+                    .collect(),
+            ),
+            UrlParam::Array(values) => {
+                VerbParam::Array(values.into_iter().map(VerbParam::from).collect())
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct UrlParams {
-    hashmap: HashMap<&'static str, UrlParam>,
+    hashmap: HashMap<String, UrlParam>,
+    raw: HashMap<String, String>,
 }
 
 impl Index<&'static str> for UrlParams {
@@ -83,19 +746,70 @@ impl Index<&'static str> for UrlParams {
     }
 }
 
+impl Default for UrlParams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UrlParams {
     pub fn new() -> Self {
         UrlParams {
             hashmap: HashMap::new(),
+            raw: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, key: &str, value: UrlParam) {
+        self.hashmap.insert(key.to_string(), value);
+    }
+
+    // adds a param along with the exact text it was parsed from
+    pub fn add_raw(&mut self, key: &str, value: UrlParam, raw: &str) {
+        self.hashmap.insert(key.to_string(), value);
+        self.raw.insert(key.to_string(), raw.to_string());
+    }
+
+    pub fn raw(&self, key: &str) -> Option<&str> {
+        self.raw.get(key).map(|raw| raw.as_str())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&UrlParam> {
+        self.hashmap.get(key)
+    }
+
+    // typed counterparts to `get`, returning `None` when the param is missing or was captured
+    // as a different type
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        match self.get(key)? {
+            UrlParam::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, key: &str) -> Option<f64> {
+        match self.get(key)? {
+            UrlParam::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            UrlParam::String(value) => Some(value),
+            _ => None,
         }
     }
 
-    pub fn add(&mut self, key: &'static str, value: UrlParam) {
-        self.hashmap.insert(key, value);
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            UrlParam::Bool(value) => Some(*value),
+            _ => None,
+        }
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Hash, Eq)]
+#[derive(Debug, PartialEq, Clone, Hash, Eq, PartialOrd, Ord)]
 pub enum Verb {
     Get,
     Post,
@@ -105,26 +819,95 @@ pub enum Verb {
     Head,
 }
 
-#[derive(Debug, PartialEq, Clone, Hash, Eq)]
+impl Verb {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verb::Get => "GET",
+            Verb::Post => "POST",
+            Verb::Put => "PUT",
+            Verb::Patch => "PATCH",
+            Verb::Delete => "DELETE",
+            Verb::Head => "HEAD",
+        }
+    }
+
+    // the verb for an HTTP method name such as `GET`, which is case-sensitive
+    pub fn parse(method: &str) -> Option<Verb> {
+        Verb::all().into_iter().find(|verb| verb.as_str() == method)
+    }
+
+    pub fn all() -> Vec<Verb> {
+        vec![
+            Verb::Get,
+            Verb::Post,
+            Verb::Put,
+            Verb::Patch,
+            Verb::Delete,
+            Verb::Head,
+        ]
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum RouteVar {
     Int(&'static str),
     Float(&'static str),
+    Bool(&'static str),
+    Date(&'static str),
+    Bytes(&'static str),
+    #[cfg(feature = "uuid")]
+    Uuid(&'static str),
+    Regex(&'static str, Regex),
+    List(&'static str),
     String(&'static str),
+    Repeat(&'static str),
+    Wildcard(&'static str),
+}
+
+// regexes can't be compared or hashed directly, so constrained vars go by their pattern
+impl PartialEq for RouteVar {
+    fn eq(&self, other: &RouteVar) -> bool {
+        self.sigil() == other.sigil()
+            && self.name() == other.name()
+            && self.pattern() == other.pattern()
+    }
+}
+
+impl Eq for RouteVar {}
+
+impl Hash for RouteVar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sigil().hash(state);
+        self.name().hash(state);
+        self.pattern().hash(state);
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Hash, Eq)]
 pub enum RoutePart {
     Path(String),
+    Bool,
     Int,
     Float,
+    Date,
+    Bytes,
+    #[cfg(feature = "uuid")]
+    Uuid,
+    Regex(String),
+    List,
     String,
+    Repeat,
+    Wildcard,
 }
 
 #[derive(Debug, PartialEq, Clone, Hash, Eq)]
 pub struct RouteKey {
     pub domain: Option<String>,
+    // the sorted members for routes registered via `RouteBuilder::domains`, in place of `domain`
+    pub domain_set: Vec<String>,
     pub parts: Vec<RoutePart>,
     pub verb: Verb,
+    pub query: Vec<String>,
 }
 
 impl RouteKey {
@@ -140,148 +923,2857 @@ impl RouteKey {
         &self.domain
     }
 
-    pub fn from_path(verb: Verb, path: &String) -> Result<RouteKey, &'static str> {
+    pub fn from_path(verb: Verb, path: &str) -> Result<RouteKey, RouteError> {
         RouteKey::new(verb, path, None)
     }
 
-    pub fn new(
-        verb: Verb,
-        path: &String,
-        domain: Option<&String>,
-    ) -> Result<RouteKey, &'static str> {
+    pub fn new(verb: Verb, path: &str, domain: Option<&str>) -> Result<RouteKey, RouteError> {
         if !PATH_REG.is_match(path) {
-            return Err("invalid route format!");
+            return Err(path_error(path));
         }
         let domain = match domain {
-            Some(dom) => {
-                let dom = dom.to_lowercase();
-                if !DOM_REG_SIMPLE.is_match(&dom) && !DOM_REG_WILDCARD.is_match(&dom) {
-                    return Err("invalid domain!");
-                }
-                Some(dom.to_string())
-            }
+            Some(dom) => Some(parse_domain(dom, |dom| DOM_REGEXES.is_match(dom))?),
             None => None,
         };
         let mut route_key = RouteKey {
             domain: domain.clone(),
+            domain_set: Vec::new(),
             parts: Vec::new(),
             verb: verb.clone(),
+            query: Vec::new(),
         };
         for token in path.split('/') {
-            if token.len() == 0 {
+            if token.is_empty() {
                 continue;
             }
-            if VAR_INT.is_match(&token) {
+            if token == "true" || token == "false" {
+                route_key.parts.push(RoutePart::Bool);
+            } else if VAR_INT.is_match(token) {
                 route_key.parts.push(RoutePart::Int);
-            } else if VAR_FLOAT.is_match(&token) {
+            } else if VAR_FLOAT.is_match(token) {
                 route_key.parts.push(RoutePart::Float);
             } else {
-                route_key.parts.push(RoutePart::Path(token.to_string()));
+                route_key
+                    .parts
+                    .push(RoutePart::Path(percent_decode(token).into_owned()));
             }
         }
         Ok(route_key)
     }
 }
 
-#[derive(Clone)]
-pub struct Route {
-    pub domain: Option<String>,
-    pub vars: Vec<RouteVar>,
-    pub verb: Verb,
-    pub target: Endpoint,
-}
+// the key part a var is filed under and how to build the var from its name
+type Declares = (RoutePart, fn(&'static str) -> RouteVar);
 
-pub struct RouteBuilder<'a> {
-    domain: Option<&'static str>,
-    verb: Verb,
-    path: &'static str,
-    router: &'a mut Router,
+// a character that opens a var segment, with the key part and var it declares
+struct Sigil {
+    sigil: char,
+    // `None` for a sigil reserved by a feature that isn't enabled
+    declares: Option<Declares>,
+    // catch-all and repeat vars may only be the last segment
+    trailing: bool,
 }
 
-impl<'a> RouteBuilder<'a> {
-    pub fn domain(mut self, domain: &'static str) -> RouteBuilder<'a> {
-        self.domain = Some(domain);
-        self
-    }
+// every var sigil. `PATH_REG`, prefixes and both halves of route parsing all go by this, so a
+// new var type only needs adding here. `#` also covers constrained `#name<pattern>` vars.
+const SIGILS: &[Sigil] = &[
+    // integer var
+    Sigil {
+        sigil: ':',
+        declares: Some((RoutePart::Int, RouteVar::Int)),
+        trailing: false,
+    },
+    // string var
+    Sigil {
+        sigil: '#',
+        declares: Some((RoutePart::String, RouteVar::String)),
+        trailing: false,
+    },
+    // float var
+    Sigil {
+        sigil: ';',
+        declares: Some((RoutePart::Float, RouteVar::Float)),
+        trailing: false,
+    },
+    // bool var
+    Sigil {
+        sigil: '!',
+        declares: Some((RoutePart::Bool, RouteVar::Bool)),
+        trailing: false,
+    },
+    // date var
+    Sigil {
+        sigil: '@',
+        declares: Some((RoutePart::Date, RouteVar::Date)),
+        trailing: false,
+    },
+    // base64url var
+    Sigil {
+        sigil: '$',
+        declares: Some((RoutePart::Bytes, RouteVar::Bytes)),
+        trailing: false,
+    },
+    // uuid var
+    #[cfg(feature = "uuid")]
+    Sigil {
+        sigil: '^',
+        declares: Some((RoutePart::Uuid, RouteVar::Uuid)),
+        trailing: false,
+    },
+    #[cfg(not(feature = "uuid"))]
+    Sigil {
+        sigil: '^',
+        declares: None,
+        trailing: false,
+    },
+    // list var
+    Sigil {
+        sigil: ',',
+        declares: Some((RoutePart::List, RouteVar::List)),
+        trailing: false,
+    },
+    // repeat var
+    Sigil {
+        sigil: '+',
+        declares: Some((RoutePart::Repeat, RouteVar::Repeat)),
+        trailing: true,
+    },
+    // catch-all var
+    Sigil {
+        sigil: '*',
+        declares: Some((RoutePart::Wildcard, RouteVar::Wildcard)),
+        trailing: true,
+    },
+];
 
-    pub fn verb(mut self, verb: Verb) -> RouteBuilder<'a> {
-        self.verb = verb;
-        self
-    }
+fn is_sigil(c: char) -> bool {
+    SIGILS.iter().any(|sigil| sigil.sigil == c)
+}
 
-    pub fn post(mut self) -> RouteBuilder<'a> {
-        self.verb = Verb::Post;
-        self
+// what a route path token declares: `None` for a literal, or the sigil's key part and var
+fn sigil_of(token: &str) -> Result<Option<&'static Declares>, RouteError> {
+    let first = token.chars().next().unwrap();
+    match SIGILS.iter().find(|sigil| sigil.sigil == first) {
+        Some(sigil) => match &sigil.declares {
+            Some(declares) => Ok(Some(declares)),
+            None => Err(RouteError::InvalidPathFormat),
+        },
+        None => Ok(None),
     }
+}
 
-    pub fn get(mut self) -> RouteBuilder<'a> {
-        self.verb = Verb::Get;
-        self
+// the name and pattern of a `#name<pattern>` token
+fn constrained(token: &str) -> Option<(&str, &str)> {
+    match token.strip_prefix('#')?.split_once('<') {
+        Some((name, pattern)) if pattern.ends_with('>') => {
+            Some((name, &pattern[..pattern.len() - 1]))
+        }
+        _ => None,
     }
+}
 
-    pub fn put(mut self) -> RouteBuilder<'a> {
-        self.verb = Verb::Put;
-        self
+// the key part a single route path token is filed under
+fn route_part(token: &str) -> Result<RoutePart, RouteError> {
+    if let Some((_, pattern)) = constrained(token) {
+        return Ok(RoutePart::Regex(pattern.to_string()));
     }
+    Ok(match sigil_of(token)? {
+        Some((part, _)) => part.clone(),
+        // literals may be given encoded, as they would appear in a request
+        None => RoutePart::Path(percent_decode(token).into_owned()),
+    })
+}
 
-    pub fn patch(mut self) -> RouteBuilder<'a> {
-        self.verb = Verb::Patch;
-        self
+// compiles the constraint of a `#name<pattern>` var, anchored so it must match the whole
+// segment
+fn constraint(pattern: &str) -> Result<Regex, RouteError> {
+    let mut cache = CONSTRAINTS.lock().unwrap();
+    if let Some(regex) = cache.get(pattern) {
+        return Ok(regex.clone());
     }
+    let regex = match Regex::new(&format!(r"\A(?:{})\z", pattern)) {
+        Ok(regex) => regex,
+        Err(_) => return Err(RouteError::InvalidConstraint),
+    };
+    cache.insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
 
-    pub fn delete(mut self) -> RouteBuilder<'a> {
-        self.verb = Verb::Delete;
-        self
+// explains why `path` failed `PATH_REG`, singling out catch-all and repeat vars that aren't
+// in trailing position
+fn path_error(path: &str) -> RouteError {
+    let mut tokens = path.split('/').filter(|token| !token.is_empty());
+    tokens.next_back();
+    let trailing = |c| {
+        SIGILS
+            .iter()
+            .any(|sigil| sigil.trailing && sigil.sigil == c)
+    };
+    if tokens.any(|token| token.starts_with(trailing)) {
+        RouteError::WildcardNotLast
+    } else {
+        RouteError::InvalidPathFormat
     }
+}
 
-    pub fn route(self, target: Endpoint) -> Result<(), &'static str> {
-        self.router.route(self.domain, self.verb, self.path, target)
+// the default domain grammar: plain domains plus `*.` and `**.` wildcards
+#[derive(Clone)]
+struct DomainRegexes {
+    simple: Regex,
+    wildcard: Regex,
+}
+
+impl DomainRegexes {
+    fn new() -> Self {
+        DomainRegexes {
+            simple: Regex::new(r"\A([^\.\*\s]+\.[^\.\s]+)+\z").unwrap(),
+            wildcard: Regex::new(r"\A\*\*?\.([^\.\*\s]+\.[^\.\s]+)+\z").unwrap(),
+        }
+    }
+
+    fn is_match(&self, domain: &str) -> bool {
+        self.simple.is_match(domain) || self.wildcard.is_match(domain)
+    }
+}
+
+fn parse_domain(domain: &str, valid: impl Fn(&str) -> bool) -> Result<String, RouteError> {
+    let domain = domain.to_lowercase();
+    if !valid(&domain) {
+        return Err(RouteError::InvalidDomain);
+    }
+    Ok(domain)
+}
+
+// a wildcard domain such as `*.example.com` stands in for exactly one leading label, so it
+// matches `api.example.com` but neither the apex `example.com` nor `a.b.example.com`, while
+// `**.example.com` stands in for one or more labels and so also matches `a.b.example.com`
+fn domain_matches(pattern: &str, host: &str) -> bool {
+    let (suffix, multi_label) = match pattern.strip_prefix("**.") {
+        Some(suffix) => (suffix, true),
+        None => match pattern.strip_prefix("*.") {
+            Some(suffix) => (suffix, false),
+            None => return pattern.eq_ignore_ascii_case(host),
+        },
+    };
+    let host = host.to_lowercase();
+    match host.strip_suffix(suffix) {
+        Some(labels) => match labels.strip_suffix('.') {
+            Some(labels) => !labels.is_empty() && (multi_label || !labels.contains('.')),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+// a single param as it would appear in a path segment, encoded
+fn url_segment(param: &UrlParam) -> Option<String> {
+    Some(match param {
+        UrlParam::String(value) => percent_encode(value),
+        UrlParam::Int(value) => value.to_string(),
+        // debug formatting keeps the decimal point, so whole floats still read as floats
+        UrlParam::Float(value) => format!("{:?}", value),
+        UrlParam::Bool(value) => value.to_string(),
+        UrlParam::Date(date) => date.to_string(),
+        UrlParam::Bytes(bytes) => encode_base64url(bytes),
+        #[cfg(feature = "uuid")]
+        UrlParam::Uuid(uuid) => uuid.to_string(),
+        UrlParam::Array(_) => return None,
+    })
+}
+
+// encodes with the url-safe base64 alphabet, without padding
+fn encode_base64url(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut encoded = String::with_capacity(bytes.len() * 4 / 3 + 1);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for b in bytes {
+        buffer = (buffer << 8) | *b as u32;
+        bits += 8;
+        while bits >= 6 {
+            bits -= 6;
+            encoded.push(ALPHABET[(buffer >> bits) as usize & 63] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[(buffer << (6 - bits)) as usize & 63] as char);
+    }
+    encoded
+}
+
+// decodes the url-safe base64 alphabet (`-` and `_` in place of `+` and `/`), with or
+// without `=` padding
+fn decode_base64url(token: &str) -> Option<Vec<u8>> {
+    let token = token.trim_end_matches('=');
+    if token.len() % 4 == 1 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(token.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in token.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+fn literal_eq(literal: &str, token: &str, case_insensitive: bool) -> bool {
+    if case_insensitive {
+        literal.to_lowercase() == token.to_lowercase()
+    } else {
+        literal == token
+    }
+}
+
+fn parse_float(token: &str) -> Option<f64> {
+    if VAR_INT.is_match(token) || VAR_FLOAT.is_match(token) {
+        token.parse().ok()
+    } else {
+        None
+    }
+}
+
+// only the canonical hyphenated 8-4-4-4-12 form, not the other forms `parse_str` accepts
+#[cfg(feature = "uuid")]
+fn parse_uuid(token: &str) -> Option<uuid::Uuid> {
+    let canonical = token.len() == 36
+        && token
+            .char_indices()
+            .all(|(i, c)| matches!(i, 8 | 13 | 18 | 23) == (c == '-'));
+    if canonical {
+        uuid::Uuid::parse_str(token).ok()
+    } else {
+        None
+    }
+}
+
+// the non-empty elements of a `,name` segment, or `None` if there are none and empty lists
+// aren't allowed
+fn split_list(token: &str, delimiter: char, allow_empty: bool) -> Option<UrlParam> {
+    let elements: Vec<UrlParam> = token
+        .split(delimiter)
+        .filter(|element| !element.is_empty())
+        .map(|element| UrlParam::String(element.to_string()))
+        .collect();
+    if elements.is_empty() && !allow_empty {
+        return None;
+    }
+    Some(UrlParam::Array(elements))
+}
+
+fn parse_bool(token: &str) -> Option<bool> {
+    match token {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// whether a typed slot would capture the literal segment `literal`
+fn part_accepts(part: &RoutePart, literal: &str) -> bool {
+    match part {
+        RoutePart::Path(other) => other == literal,
+        RoutePart::Bool => parse_bool(literal).is_some(),
+        RoutePart::Int => VAR_INT.is_match(literal),
+        RoutePart::Float => parse_float(literal).is_some(),
+        RoutePart::Date => Date::parse(literal).is_some(),
+        RoutePart::Bytes => decode_base64url(literal).is_some(),
+        #[cfg(feature = "uuid")]
+        RoutePart::Uuid => parse_uuid(literal).is_some(),
+        RoutePart::Regex(pattern) => constraint(pattern).is_ok_and(|re| re.is_match(literal)),
+        RoutePart::List | RoutePart::String | RoutePart::Repeat | RoutePart::Wildcard => true,
+    }
+}
+
+// whether some segment could be matched by both parts. Pairs of typed slots are assumed to
+// overlap unless their grammars are known to be disjoint (dates never parse as numbers).
+fn parts_overlap(a: &RoutePart, b: &RoutePart) -> bool {
+    match (a, b) {
+        (RoutePart::Path(literal), part) | (part, RoutePart::Path(literal)) => {
+            part_accepts(part, literal)
+        }
+        (RoutePart::Date, RoutePart::Bool | RoutePart::Int | RoutePart::Float)
+        | (RoutePart::Bool | RoutePart::Int | RoutePart::Float, RoutePart::Date) => false,
+        _ => true,
+    }
+}
+
+type MatrixParams<'p> = Vec<(&'p str, &'p str)>;
+
+#[derive(Clone)]
+pub struct Route {
+    pub domain: Option<String>,
+    pub domain_set: Vec<String>,
+    pub parts: Vec<RoutePart>,
+    pub vars: Vec<RouteVar>,
+    pub verb: Verb,
+    pub target: Endpoint,
+    pub format: bool,
+    pub formats: Vec<String>,
+    pub split_wildcard: bool,
+    pub list_delimiter: char,
+    pub empty_lists: bool,
+    pub date_segments: bool,
+    pub query: Vec<QueryParam>,
+    pub strict_query: bool,
+    pub optional: bool,
+    pub selector: Option<Selector>,
+    pub fallible: Option<FallibleEndpoint>,
+    pub timeout: Option<Duration>,
+    pub max_body: Option<usize>,
+    pub handler: Option<&'static str>,
+    pub metadata: HashMap<String, String>,
+    extensions: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    pub tags: Vec<String>,
+    pub headers: Vec<(String, String)>,
+    pub priority: i32,
+    case_insensitive: bool,
+    seq: usize,
+}
+
+impl RouteVar {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RouteVar::Int(name)
+            | RouteVar::Float(name)
+            | RouteVar::Bool(name)
+            | RouteVar::Date(name)
+            | RouteVar::Bytes(name)
+            | RouteVar::Regex(name, _)
+            | RouteVar::List(name)
+            | RouteVar::String(name)
+            | RouteVar::Repeat(name)
+            | RouteVar::Wildcard(name) => name,
+            #[cfg(feature = "uuid")]
+            RouteVar::Uuid(name) => name,
+        }
+    }
+
+    pub fn sigil(&self) -> char {
+        match self {
+            RouteVar::Int(_) => ':',
+            RouteVar::Float(_) => ';',
+            RouteVar::Bool(_) => '!',
+            RouteVar::Date(_) => '@',
+            RouteVar::Bytes(_) => '$',
+            #[cfg(feature = "uuid")]
+            RouteVar::Uuid(_) => '^',
+            RouteVar::Regex(_, _) | RouteVar::String(_) => '#',
+            RouteVar::List(_) => ',',
+            RouteVar::Repeat(_) => '+',
+            RouteVar::Wildcard(_) => '*',
+        }
+    }
+
+    // the constraint of a `#name<pattern>` var
+    pub fn pattern(&self) -> Option<&str> {
+        match self {
+            RouteVar::Regex(_, regex) => {
+                let anchored = regex.as_str();
+                Some(&anchored[5..anchored.len() - 3])
+            }
+            _ => None,
+        }
+    }
+}
+
+impl RoutePart {
+    pub fn is_dynamic(&self) -> bool {
+        !matches!(self, RoutePart::Path(_))
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            RoutePart::Path(_) => 0,
+            RoutePart::Bool => 1,
+            RoutePart::Int => 2,
+            RoutePart::Float => 3,
+            RoutePart::Date => 4,
+            #[cfg(feature = "uuid")]
+            RoutePart::Uuid => 5,
+            RoutePart::Bytes => 6,
+            RoutePart::Regex(_) => 7,
+            RoutePart::List => 8,
+            RoutePart::String => 9,
+            RoutePart::Repeat => 10,
+            RoutePart::Wildcard => 11,
+        }
+    }
+}
+
+impl Route {
+    pub fn meta(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(|value| value.as_str())
+    }
+
+    // typed counterpart to `meta`, holding at most one value per type
+    pub fn insert_ext<T: Any + Send + Sync>(&mut self, value: T) {
+        self.extensions.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get_ext<T: Any>(&self) -> Option<&T> {
+        self.extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn summary(&self) -> Option<&str> {
+        self.meta("summary")
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.meta("description")
+    }
+
+    // a concrete request path (with query) this route should match, built from sample values
+    // for each var. Vars can be given their own sample with `RouteBuilder::example`, which
+    // constrained `#name<pattern>` vars whose pattern the default sample fails need.
+    pub fn example_path(&self) -> String {
+        let mut vars = self.vars.iter();
+        let mut segments: Vec<String> = Vec::new();
+        for part in self.parts.iter() {
+            let sample = match part {
+                RoutePart::Path(literal) => {
+                    segments.push(literal.clone());
+                    continue;
+                }
+                RoutePart::Bool => "true".to_string(),
+                RoutePart::Int => "42".to_string(),
+                RoutePart::Float => "1.5".to_string(),
+                RoutePart::Date if self.date_segments => "2000/01/31".to_string(),
+                RoutePart::Date => "2000-01-31".to_string(),
+                #[cfg(feature = "uuid")]
+                RoutePart::Uuid => "00000000-0000-0000-0000-000000000000".to_string(),
+                RoutePart::Bytes => "AA".to_string(),
+                RoutePart::List => format!("a{}b", self.list_delimiter),
+                // an odd length keeps it from also being valid base64url
+                RoutePart::Regex(_) | RoutePart::String => "hello".to_string(),
+                RoutePart::Repeat => "a/b".to_string(),
+                RoutePart::Wildcard => "a/b.txt".to_string(),
+            };
+            let name = vars.next().map_or("", |var| var.name());
+            match self.meta(&format!("example.{}", name)) {
+                Some(example) => segments.push(example.to_string()),
+                None => segments.push(sample),
+            }
+        }
+        let mut path = format!("/{}", segments.join("/"));
+        let query: Vec<String> = self
+            .query
+            .iter()
+            .filter(|param| !param.absent && param.default.is_none())
+            .map(|param| {
+                let sample = match param.kind {
+                    QueryType::Int => "42",
+                    QueryType::Float => "1.5",
+                    QueryType::Bool => "true",
+                    QueryType::String => "hello",
+                };
+                format!("{}={}", param.name, sample)
+            })
+            .collect();
+        if !query.is_empty() {
+            path.push('?');
+            path.push_str(&query.join("&"));
+        }
+        path
+    }
+
+    // set with `RouteBuilder::name`, for building urls with `Router::url_for`
+    pub fn name(&self) -> Option<&str> {
+        self.meta("name")
+    }
+
+    // the concrete path this route would match with the given params, the inverse of
+    // matching. `None` if a var has no param of the type its sigil captures.
+    pub fn url_for(&self, params: &UrlParams) -> Option<String> {
+        let mut vars = self.vars.iter();
+        let mut segments: Vec<String> = Vec::new();
+        for (i, part) in self.parts.iter().enumerate() {
+            if let RoutePart::Path(literal) = part {
+                segments.push(percent_encode(literal));
+                continue;
+            }
+            let var = vars.next()?;
+            let param = match params.get(var.name()) {
+                Some(param) => param,
+                None if self.optional && i == self.parts.len() - 1 => break,
+                None => return None,
+            };
+            let segment = match (part, param) {
+                (RoutePart::Int, UrlParam::Int(_))
+                | (RoutePart::Float, UrlParam::Float(_))
+                | (RoutePart::Bool, UrlParam::Bool(_))
+                | (RoutePart::Bytes, UrlParam::Bytes(_)) => url_segment(param)?,
+                #[cfg(feature = "uuid")]
+                (RoutePart::Uuid, UrlParam::Uuid(_)) => url_segment(param)?,
+                (RoutePart::Date, UrlParam::Date(date)) if self.date_segments => {
+                    date.to_string().replace('-', "/")
+                }
+                (RoutePart::Date, UrlParam::Date(_)) => url_segment(param)?,
+                (RoutePart::String, UrlParam::String(value)) if !value.is_empty() => {
+                    percent_encode(value)
+                }
+                (RoutePart::Regex(_), UrlParam::String(value)) => match var {
+                    RouteVar::Regex(_, regex) if regex.is_match(value) => percent_encode(value),
+                    _ => return None,
+                },
+                (RoutePart::List, UrlParam::Array(values)) => {
+                    let items: Option<Vec<String>> = values.iter().map(url_segment).collect();
+                    items?.join(&self.list_delimiter.to_string())
+                }
+                (RoutePart::Repeat, UrlParam::Array(values))
+                | (RoutePart::Wildcard, UrlParam::Array(values))
+                    if !values.is_empty() =>
+                {
+                    let items: Option<Vec<String>> = values.iter().map(url_segment).collect();
+                    items?.join("/")
+                }
+                (RoutePart::Wildcard, UrlParam::String(value)) => value
+                    .split('/')
+                    .map(percent_encode)
+                    .collect::<Vec<String>>()
+                    .join("/"),
+                _ => return None,
+            };
+            segments.push(segment);
+        }
+        Some(format!("/{}", segments.join("/")))
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.meta("deprecated") == Some("true")
+    }
+
+    pub fn sunset(&self) -> Option<&str> {
+        self.meta("sunset")
+    }
+
+    pub fn is_static(&self) -> bool {
+        !self.format && !self.parts.iter().any(|part| part.is_dynamic())
+    }
+
+    pub fn to_path_string(&self) -> String {
+        let mut vars = self.vars.iter();
+        let mut path = String::new();
+        for part in self.parts.iter() {
+            path.push('/');
+            match part {
+                RoutePart::Path(literal) => path.push_str(literal),
+                _ => {
+                    let var = vars.next().unwrap();
+                    path.push(var.sigil());
+                    path.push_str(var.name());
+                    if let Some(pattern) = var.pattern() {
+                        path.push_str(&format!("<{}>", pattern));
+                    }
+                }
+            }
+        }
+        if self.optional {
+            path.push('?');
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+        path
+    }
+
+    fn matches_domain(&self, domain: Option<&str>, host_optional: bool) -> bool {
+        let domains = self.domains();
+        match domain {
+            _ if domains.is_empty() => true,
+            Some(incoming) => domains.iter().any(|dom| domain_matches(dom, incoming)),
+            None => host_optional,
+        }
+    }
+
+    // the domain as shown in snapshots: `-` for none, `{a,b}` for a domain set
+    fn domain_label(&self) -> String {
+        match (&self.domain, self.domain_set.is_empty()) {
+            (_, false) => format!("{{{}}}", self.domain_set.join(",")),
+            (Some(dom), true) => dom.clone(),
+            (None, true) => "-".to_string(),
+        }
+    }
+
+    // a canonical one-line description of everything about the route except its endpoint
+    fn snapshot_line(&self) -> String {
+        let mut line = format!(
+            "{} {} {}",
+            self.verb.as_str(),
+            self.domain_label(),
+            self.to_path_string()
+        );
+        let query: Vec<String> = self.query.iter().map(|param| param.to_string()).collect();
+        if !query.is_empty() {
+            line.push('?');
+            line.push_str(&query.join("&"));
+        }
+        if self.format {
+            line.push_str(&format!(" format({})", self.formats.join(",")));
+        }
+        if self.split_wildcard {
+            line.push_str(" split");
+        }
+        if self.list_delimiter != ',' {
+            line.push_str(&format!(" list_delimiter({})", self.list_delimiter));
+        }
+        if self.empty_lists {
+            line.push_str(" empty_lists");
+        }
+        if self.date_segments {
+            line.push_str(" date_segments");
+        }
+        if self.strict_query {
+            line.push_str(" strict_query");
+        }
+        if self.selector.is_some() {
+            line.push_str(" dynamic");
+        }
+        if let Some(handler) = self.handler {
+            line.push_str(&format!(" handler({})", handler));
+        }
+        if let Some(timeout) = self.timeout {
+            line.push_str(&format!(" timeout({}ms)", timeout.as_millis()));
+        }
+        if let Some(bytes) = self.max_body {
+            line.push_str(&format!(" max_body({})", bytes));
+        }
+        if self.priority != 0 {
+            line.push_str(&format!(" priority({})", self.priority));
+        }
+        if !self.tags.is_empty() {
+            line.push_str(&format!(" tags({})", self.tags.join(",")));
+        }
+        let mut metadata: Vec<String> = self
+            .metadata
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        if !metadata.is_empty() {
+            metadata.sort_unstable();
+            line.push_str(&format!(" meta({})", metadata.join(",")));
+        }
+        line
+    }
+
+    pub fn captures(&self, path: &str) -> Option<UrlParams> {
+        let (path, _) = split_query(path);
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        self.capture(&segments)
+    }
+
+    // names of the params whose captured values differ between two concrete paths, or `None`
+    // if either path doesn't match this route
+    pub fn param_diff(&self, a: &str, b: &str) -> Option<Vec<&'static str>> {
+        let a = self.captures(a)?;
+        let b = self.captures(b)?;
+        let mut names: Vec<&'static str> = self
+            .vars
+            .iter()
+            .map(|var| var.name())
+            .chain(Some("format").filter(|_| self.format))
+            .filter(|name| a.get(name) != b.get(name))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        Some(names)
+    }
+
+    // the segments of the canonical path for a request matched on `segments`: literals as
+    // registered, everything else as requested
+    fn canonical_segments(&self, segments: &[&str]) -> Vec<String> {
+        let mut canonical = Vec::new();
+        let mut i = 0;
+        for part in self.parts.iter() {
+            let rest = &segments[i.min(segments.len())..];
+            let taken = match part {
+                RoutePart::Path(literal) => match rest.first() {
+                    Some(token) => {
+                        let decoded = percent_decode(token);
+                        if decoded.starts_with(literal.as_str()) {
+                            canonical.push(token.to_string());
+                        } else {
+                            // keep any format extension the literal was matched without
+                            let ext = decoded.get(literal.len()..).unwrap_or("");
+                            canonical.push(format!("{}{}", literal, ext));
+                        }
+                        1
+                    }
+                    None => 0,
+                },
+                RoutePart::Repeat | RoutePart::Wildcard => rest.len(),
+                RoutePart::Date if self.date_segments => rest.len().min(3),
+                _ => rest.len().min(1),
+            };
+            if !matches!(part, RoutePart::Path(_)) {
+                canonical.extend(rest[..taken].iter().map(|token| token.to_string()));
+            }
+            i += taken;
+        }
+        canonical
+    }
+
+    fn capture(&self, segments: &[&str]) -> Option<UrlParams> {
+        if self.format {
+            if let Some((last, rest)) = segments.split_last() {
+                if let Some((stem, ext)) = last.rsplit_once('.') {
+                    let allowed = self.formats.is_empty() || self.formats.iter().any(|f| f == ext);
+                    if !stem.is_empty() && !ext.is_empty() && allowed {
+                        let mut stripped = rest.to_vec();
+                        stripped.push(stem);
+                        if let Some(mut params) = self.capture_parts(&stripped) {
+                            params.add_raw("format", UrlParam::String(ext.to_string()), ext);
+                            return Some(params);
+                        }
+                    }
+                }
+            }
+        }
+        self.capture_parts(segments)
+    }
+
+    fn capture_parts(&self, segments: &[&str]) -> Option<UrlParams> {
+        let mut params = UrlParams::new();
+        let mut vars = self.vars.iter();
+        let mut i = 0;
+        for part in self.parts.iter() {
+            if let RoutePart::Path(literal) = part {
+                match segments.get(i) {
+                    Some(token) if literal_eq(literal, token, self.case_insensitive) => {}
+                    _ => return None,
+                }
+                i += 1;
+                continue;
+            }
+            let var = vars.next().unwrap();
+            let name = var.name();
+            if self.optional && vars.len() == 0 && i == segments.len() {
+                break;
+            }
+            if let RoutePart::Wildcard = part {
+                let rest = segments[i..].join("/");
+                let value = if self.split_wildcard {
+                    UrlParam::Array(
+                        segments[i..]
+                            .iter()
+                            .map(|segment| UrlParam::String(segment.to_string()))
+                            .collect(),
+                    )
+                } else {
+                    UrlParam::String(rest.clone())
+                };
+                params.add_raw(name, value, &rest);
+                if let Some(ext) = file_ext(&rest) {
+                    if self.vars.iter().all(|var| var.name() != EXT_PARAM) {
+                        params.add_raw(EXT_PARAM, UrlParam::String(ext.to_string()), ext);
+                    }
+                }
+                i = segments.len();
+                continue;
+            }
+            if let RoutePart::Repeat = part {
+                let rest = &segments[i..];
+                if rest.is_empty() {
+                    return None;
+                }
+                let values = rest
+                    .iter()
+                    .map(|segment| UrlParam::String(segment.to_string()))
+                    .collect();
+                params.add_raw(name, UrlParam::Array(values), &rest.join("/"));
+                i = segments.len();
+                continue;
+            }
+            if let (RoutePart::Date, true) = (part, self.date_segments) {
+                let fields = segments.get(i..i + 3)?;
+                let date = Date::from_fields(fields[0], fields[1], fields[2])?;
+                params.add_raw(name, UrlParam::Date(date), &fields.join("/"));
+                i += 3;
+                continue;
+            }
+            let token = *segments.get(i)?;
+            let value = match part {
+                RoutePart::Int => UrlParam::Int(token.parse().ok()?),
+                RoutePart::Float => UrlParam::Float(parse_float(token)?),
+                RoutePart::Bool => UrlParam::Bool(parse_bool(token)?),
+                RoutePart::Date => UrlParam::Date(Date::parse(token)?),
+                RoutePart::Bytes => UrlParam::Bytes(decode_base64url(token)?),
+                #[cfg(feature = "uuid")]
+                RoutePart::Uuid => UrlParam::Uuid(parse_uuid(token)?),
+                RoutePart::Regex(_) => match var {
+                    RouteVar::Regex(_, regex) if regex.is_match(token) => {
+                        UrlParam::String(token.to_string())
+                    }
+                    _ => return None,
+                },
+                RoutePart::List => split_list(token, self.list_delimiter, self.empty_lists)?,
+                RoutePart::String => UrlParam::String(token.to_string()),
+                _ => return None,
+            };
+            params.add_raw(name, value, token);
+            i += 1;
+        }
+        if i != segments.len() {
+            return None;
+        }
+        Some(params)
+    }
+
+    fn accepts_query(&self, query: &VerbParams) -> bool {
+        self.query.iter().all(|param| match query.get(&param.name) {
+            Some(_) if param.absent => false,
+            Some(VerbParam::String(value)) => param.kind.parse(value).is_some(),
+            Some(_) => true,
+            None => param.absent || param.default.is_some(),
+        })
+    }
+
+    // the first param in `query` that isn't declared in the route's query spec
+    fn undeclared_query<'q>(&self, query: &'q VerbParams) -> Option<&'q str> {
+        query
+            .hashmap
+            .keys()
+            .find(|key| !self.query.iter().any(|param| &param.name == *key))
+            .map(|key| key.as_str())
+    }
+
+    fn apply_query(&self, query: &mut VerbParams) {
+        for param in self.query.iter() {
+            let value = match query.get(&param.name) {
+                Some(VerbParam::String(value)) => param.kind.parse(value),
+                Some(_) => None,
+                None => param.default.clone(),
+            };
+            if let Some(value) = value {
+                query.add(param.name.clone(), value);
+            }
+        }
+    }
+
+    fn call(
+        &self,
+        params: &UrlParams,
+        get: &VerbParams,
+        post: &VerbParams,
+        json_errors: bool,
+    ) -> Render {
+        let fallible = match self.fallible {
+            Some(fallible) => fallible,
+            None => {
+                let target = match self.selector {
+                    Some(selector) => selector(params, get, post),
+                    None => self.target,
+                };
+                return target(params, get, post);
+            }
+        };
+        match fallible(params, get, post) {
+            Ok(render) => render,
+            Err(err) => error_render(err.as_ref(), json_errors),
+        }
+    }
+
+    // `Less` means `self` takes precedence: higher explicit priorities win, then more specific
+    // domains (see `domain_specificity`), then literal segments beat typed slots position by
+    // position, then routes with more query constraints beat those with fewer, then earlier
+    // registrations win. Requests without a host (see `Router::set_host_optional`) skip the
+    // domain comparison, since no domain is more specific to them than another.
+    fn precedence(&self, other: &Route, host: Option<&str>) -> Ordering {
+        let specificity = match host {
+            Some(_) => other.domain_specificity().cmp(&self.domain_specificity()),
+            None => Ordering::Equal,
+        };
+        other
+            .priority
+            .cmp(&self.priority)
+            .then(specificity)
+            .then_with(|| {
+                self.parts
+                    .iter()
+                    .map(|part| part.rank())
+                    .cmp(other.parts.iter().map(|part| part.rank()))
+            })
+            .then(other.query.len().cmp(&self.query.len()))
+            .then(self.seq.cmp(&other.seq))
+    }
+
+    // higher is more specific: exact domains beat single-label wildcards (`*.example.com`),
+    // which beat multi-label wildcards (`**.example.com`), which beat domainless routes. Among
+    // wildcards of the same kind, the one with more fixed labels wins.
+    pub fn domain_specificity(&self) -> u32 {
+        // a domain set is only as specific as its least specific member
+        self.domains()
+            .into_iter()
+            .map(domain_specificity)
+            .min()
+            .unwrap_or(0)
+    }
+
+    // the domains the route is registered under: one for `domain`, several for `domains`
+    pub fn domains(&self) -> Vec<&str> {
+        match &self.domain {
+            _ if !self.domain_set.is_empty() => {
+                self.domain_set.iter().map(|dom| dom.as_str()).collect()
+            }
+            Some(dom) => vec![dom.as_str()],
+            None => Vec::new(),
+        }
+    }
+}
+
+fn domain_specificity(dom: &str) -> u32 {
+    let fixed = dom
+        .split('.')
+        .filter(|label| !label.starts_with('*'))
+        .count() as u32;
+    if dom.starts_with("**.") {
+        1000 + fixed
+    } else if dom.starts_with("*.") {
+        2000 + fixed
+    } else {
+        3000 + fixed
+    }
+}
+
+pub struct RouteMatch<'a> {
+    key: &'a RouteKey,
+    route: &'a Route,
+    params: UrlParams,
+}
+
+impl<'a> RouteMatch<'a> {
+    pub fn key(&self) -> &'a RouteKey {
+        self.key
+    }
+
+    pub fn route(&self) -> &'a Route {
+        self.route
+    }
+
+    // the endpoint handling the match. Dynamic routes ask their selector, which sees no get
+    // or post params here; use `endpoint` to pass them.
+    pub fn target(&self) -> Endpoint {
+        self.endpoint(&VerbParams::new(), &VerbParams::new())
+    }
+
+    // the endpoint for a match found for `path`, letting a selector see its query params
+    fn target_for(&self, path: &str) -> Endpoint {
+        match self.route.selector {
+            Some(_) => self.endpoint(
+                &VerbParams::from_query(split_query(path).1),
+                &VerbParams::new(),
+            ),
+            None => self.route.target,
+        }
+    }
+
+    pub fn endpoint(&self, get: &VerbParams, post: &VerbParams) -> Endpoint {
+        match self.route.selector {
+            Some(selector) => selector(&self.params, get, post),
+            None => self.route.target,
+        }
+    }
+
+    // runs the matched route's handler, including fallible ones, whose errors are rendered as
+    // plain text
+    pub fn call(&self, get: &VerbParams, post: &VerbParams) -> Render {
+        self.route.call(&self.params, get, post, false)
+    }
+
+    pub fn params(&self) -> &UrlParams {
+        &self.params
+    }
+
+    pub fn into_params(self) -> UrlParams {
+        self.params
+    }
+
+    pub fn template(&self) -> String {
+        self.route.to_path_string()
+    }
+
+    // how many segments the route's fixed prefix consumed, i.e. the number of parts ahead
+    // of a trailing catch-all or repeat (or all of them if the route has neither)
+    pub fn matched_depth(&self) -> usize {
+        self.route
+            .parts
+            .iter()
+            .position(|part| matches!(part, RoutePart::Wildcard | RoutePart::Repeat))
+            .unwrap_or(self.route.parts.len())
+    }
+
+    // a low-cardinality label for the request such as `GET /users/:id`, for logs and metrics
+    pub fn log_key(&self) -> String {
+        format!("{} {}", self.route.verb.as_str(), self.template())
+    }
+}
+
+// typestates for `RouteBuilder`: a builder can only `route` once its verb is known, which
+// builders from `Router::path` (defaulting to GET) always are but builders from `Router::on`
+// only are after `verb` or one of its shorthands
+pub struct Verbed;
+pub struct Unverbed;
+
+pub struct RouteBuilder<'a, State = Verbed> {
+    domain: Option<&'static str>,
+    domains: Vec<&'static str>,
+    verbs: Vec<Verb>,
+    // set by `RouteGroup`, whose static prefix is prepended to `path`
+    prefix: Option<&'static str>,
+    path: &'static str,
+    format: bool,
+    formats: Vec<String>,
+    split_wildcard: bool,
+    list_delimiter: char,
+    empty_lists: bool,
+    date_segments: bool,
+    strict_query: bool,
+    timeout: Option<Duration>,
+    max_body: Option<usize>,
+    priority: i32,
+    tags: Vec<String>,
+    headers: Vec<(String, String)>,
+    metadata: HashMap<String, String>,
+    extensions: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    router: &'a mut Router,
+    state: PhantomData<State>,
+}
+
+impl<'a, State> RouteBuilder<'a, State> {
+    pub fn domain(mut self, domain: &'static str) -> RouteBuilder<'a, State> {
+        self.domain = Some(domain);
+        self
+    }
+
+    // registers a single route that matches any of `domains`, in place of `domain`
+    pub fn domains(mut self, domains: &[&'static str]) -> RouteBuilder<'a, State> {
+        self.domains = domains.to_vec();
+        self
+    }
+
+    pub fn verb(self, verb: Verb) -> RouteBuilder<'a, Verbed> {
+        self.verbs(&[verb])
+    }
+
+    // registers the route once per verb; all of them or none are registered
+    pub fn verbs(self, verbs: &[Verb]) -> RouteBuilder<'a, Verbed> {
+        let mut unique: Vec<Verb> = Vec::new();
+        for verb in verbs.iter() {
+            if !unique.contains(verb) {
+                unique.push(verb.clone());
+            }
+        }
+        RouteBuilder {
+            domain: self.domain,
+            domains: self.domains,
+            verbs: unique,
+            prefix: self.prefix,
+            path: self.path,
+            format: self.format,
+            formats: self.formats,
+            split_wildcard: self.split_wildcard,
+            list_delimiter: self.list_delimiter,
+            empty_lists: self.empty_lists,
+            date_segments: self.date_segments,
+            strict_query: self.strict_query,
+            timeout: self.timeout,
+            max_body: self.max_body,
+            priority: self.priority,
+            tags: self.tags,
+            headers: self.headers,
+            metadata: self.metadata,
+            extensions: self.extensions,
+            router: self.router,
+            state: PhantomData,
+        }
+    }
+
+    pub fn post(self) -> RouteBuilder<'a, Verbed> {
+        self.verb(Verb::Post)
+    }
+
+    pub fn get(self) -> RouteBuilder<'a, Verbed> {
+        self.verb(Verb::Get)
+    }
+
+    pub fn put(self) -> RouteBuilder<'a, Verbed> {
+        self.verb(Verb::Put)
+    }
+
+    pub fn patch(self) -> RouteBuilder<'a, Verbed> {
+        self.verb(Verb::Patch)
+    }
+
+    pub fn delete(self) -> RouteBuilder<'a, Verbed> {
+        self.verb(Verb::Delete)
+    }
+
+    pub fn head(self) -> RouteBuilder<'a, Verbed> {
+        self.verb(Verb::Head)
+    }
+
+    // splits a trailing `.ext` off the final segment of incoming paths and captures it as
+    // the `format` param, so `/users/:id` also matches `/users/5.json`
+    pub fn format(mut self) -> RouteBuilder<'a, State> {
+        self.format = true;
+        self
+    }
+
+    // like `format`, but only the listed extensions are split off, so any other suffix is
+    // left as part of the final segment
+    pub fn formats(mut self, formats: &[&str]) -> RouteBuilder<'a, State> {
+        self.format = true;
+        self.formats = formats.iter().map(|format| format.to_string()).collect();
+        self
+    }
+
+    // captures a trailing `*name` as an array of the remaining segments rather than a single
+    // joined string
+    pub fn split_wildcard(mut self) -> RouteBuilder<'a, State> {
+        self.split_wildcard = true;
+        self
+    }
+
+    // splits `,name` list segments on `delimiter` rather than on commas
+    pub fn list_delimiter(mut self, delimiter: char) -> RouteBuilder<'a, State> {
+        self.list_delimiter = delimiter;
+        self
+    }
+
+    // lets a `,name` segment with no elements (e.g. just `,`) match as an empty array rather
+    // than not matching at all
+    pub fn empty_lists(mut self) -> RouteBuilder<'a, State> {
+        self.empty_lists = true;
+        self
+    }
+
+    // captures `@name` dates from three consecutive `YYYY/MM/DD` segments rather than a single
+    // `YYYY-MM-DD` segment
+    pub fn date_segments(mut self) -> RouteBuilder<'a, State> {
+        self.date_segments = true;
+        self
+    }
+
+    // when dispatched, requests carrying query params the route's query spec doesn't declare
+    // are rejected with a 400 rather than having the extras ignored
+    pub fn strict_query(mut self) -> RouteBuilder<'a, State> {
+        self.strict_query = true;
+        self
+    }
+
+    // a header added to every response dispatched to the route, unless the handler sets it
+    pub fn header(mut self, name: &str, value: &str) -> RouteBuilder<'a, State> {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // when dispatched, the endpoint runs on its own thread and the router's timeout response
+    // is rendered if it hasn't finished within `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> RouteBuilder<'a, State> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // overrides the usual precedence rules: when several routes match a request, the one
+    // with the highest priority wins regardless of how specific the others are (default 0)
+    pub fn priority(mut self, priority: i32) -> RouteBuilder<'a, State> {
+        self.priority = priority;
+        self
+    }
+
+    // tags are free-form labels, used e.g. to scope middleware via `Scope::Tag`
+    pub fn tag(mut self, tag: &str) -> RouteBuilder<'a, State> {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    pub fn meta(mut self, key: &str, value: &str) -> RouteBuilder<'a, State> {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    // attaches a typed extension to the route, see `Route::insert_ext`
+    pub fn ext<T: Any + Send + Sync>(mut self, value: T) -> RouteBuilder<'a, State> {
+        self.extensions.insert(TypeId::of::<T>(), Arc::new(value));
+        self
+    }
+
+    // names the route for `Router::url_for`
+    pub fn name(self, name: &str) -> RouteBuilder<'a, State> {
+        self.meta("name", name)
+    }
+
+    // one-line summary for generated docs, see `Router::to_manifest`
+    pub fn summary(self, summary: &str) -> RouteBuilder<'a, State> {
+        self.meta("summary", summary)
+    }
+
+    pub fn description(self, description: &str) -> RouteBuilder<'a, State> {
+        self.meta("description", description)
+    }
+
+    // the sample value `Route::example_path` uses for the var `name`
+    pub fn example(self, name: &str, value: &str) -> RouteBuilder<'a, State> {
+        self.meta(&format!("example.{}", name), value)
+    }
+
+    pub fn deprecated(self) -> RouteBuilder<'a, State> {
+        self.meta("deprecated", "true")
+    }
+
+    // marks the route deprecated along with the date it is scheduled to go away
+    pub fn sunset(self, date: &str) -> RouteBuilder<'a, State> {
+        self.deprecated().meta("sunset", date)
+    }
+
+    // request bodies larger than `bytes` are rejected with a 413 before being parsed,
+    // overriding the router-wide `set_max_body`
+    pub fn max_body(mut self, bytes: usize) -> RouteBuilder<'a, State> {
+        self.max_body = Some(bytes);
+        self
+    }
+
+    // registers `target` under every verb at once
+    pub fn any(self, target: Endpoint) -> Result<(), RouteError> {
+        self.verbs(&Verb::all()).finish(target, None)
+    }
+
+    fn build(
+        &self,
+        verb: Verb,
+        target: Endpoint,
+        handler: Option<&'static str>,
+    ) -> Result<(RouteKey, Route), RouteError> {
+        let (mut route_key, mut route) =
+            self.router
+                .parse_route(self.domain, verb, self.path, target)?;
+        if let Some(prefix) = self.prefix {
+            route_key.parts = static_prefix(prefix)?
+                .into_iter()
+                .chain(route_key.parts)
+                .collect();
+            route.parts = route_key.parts.clone();
+        }
+        route.format = self.format;
+        route.formats = self.formats.clone();
+        route.split_wildcard = self.split_wildcard;
+        route.list_delimiter = self.list_delimiter;
+        route.empty_lists = self.empty_lists;
+        route.date_segments = self.date_segments;
+        route.strict_query = self.strict_query;
+        route.timeout = self.timeout;
+        route.max_body = self.max_body;
+        route.priority = self.priority;
+        route.tags = self.tags.clone();
+        route.headers = self.headers.clone();
+        route.handler = handler;
+        route.metadata = self.metadata.clone();
+        route.extensions = self.extensions.clone();
+        if !self.domains.is_empty() {
+            let mut domains = Vec::new();
+            for dom in self.domains.iter() {
+                domains.push(self.router.parse_domain(dom)?);
+            }
+            domains.sort();
+            domains.dedup();
+            route_key.domain = None;
+            route.domain = None;
+            route_key.domain_set = domains.clone();
+            route.domain_set = domains;
+        }
+        Ok((route_key, route))
+    }
+}
+
+impl<'a> RouteBuilder<'a, Verbed> {
+    pub fn route(self, target: Endpoint) -> Result<(), RouteError> {
+        self.finish(target, None)
+    }
+
+    pub fn route_to(
+        self,
+        registry: &HandlerRegistry,
+        name: &'static str,
+    ) -> Result<(), RouteError> {
+        match registry.get(name) {
+            Some(target) => self.finish(target, Some(name)),
+            None => Err(RouteError::UnknownHandler),
+        }
+    }
+
+    fn finish(self, target: Endpoint, handler: Option<&'static str>) -> Result<(), RouteError> {
+        let mut built = Vec::new();
+        for verb in self.verbs.iter() {
+            built.push(self.build(verb.clone(), target, handler)?);
+        }
+        self.router.insert_all(built)
+    }
+}
+
+// registers routes beneath a shared static prefix and, optionally, a shared domain
+pub struct RouteGroup<'a> {
+    prefix: &'static str,
+    domain: Option<&'static str>,
+    router: &'a mut Router,
+}
+
+impl<'a> RouteGroup<'a> {
+    pub fn domain(&mut self, domain: &'static str) -> &mut RouteGroup<'a> {
+        self.domain = Some(domain);
+        self
+    }
+
+    // a builder for a route beneath the group's prefix and domain, see `Router::on`
+    pub fn on(&mut self, path: &'static str) -> RouteBuilder<'_, Unverbed> {
+        let domain = self.domain;
+        let builder = RouteBuilder {
+            prefix: Some(self.prefix),
+            ..self.router.on(path)
+        };
+        match domain {
+            Some(domain) => builder.domain(domain),
+            None => builder,
+        }
+    }
+
+    pub fn path(&mut self, path: &'static str) -> RouteBuilder<'_> {
+        self.on(path).get()
+    }
+
+    pub fn route(
+        &mut self,
+        verb: Verb,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<&mut RouteGroup<'a>, RouteError> {
+        self.on(path).verb(verb).route(target)?;
+        Ok(self)
+    }
+
+    pub fn get(
+        &mut self,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<&mut RouteGroup<'a>, RouteError> {
+        self.route(Verb::Get, path, target)
+    }
+
+    pub fn post(
+        &mut self,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<&mut RouteGroup<'a>, RouteError> {
+        self.route(Verb::Post, path, target)
+    }
+
+    pub fn put(
+        &mut self,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<&mut RouteGroup<'a>, RouteError> {
+        self.route(Verb::Put, path, target)
+    }
+
+    pub fn patch(
+        &mut self,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<&mut RouteGroup<'a>, RouteError> {
+        self.route(Verb::Patch, path, target)
+    }
+
+    pub fn delete(
+        &mut self,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<&mut RouteGroup<'a>, RouteError> {
+        self.route(Verb::Delete, path, target)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct HandlerRegistry {
+    handlers: HashMap<&'static str, Endpoint>,
+}
+
+impl HandlerRegistry {
+    pub fn new() -> Self {
+        HandlerRegistry {
+            handlers: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, name: &'static str, target: Endpoint) {
+        self.handlers.insert(name, target);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Endpoint> {
+        self.handlers.get(name).copied()
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names: Vec<&'static str> = self.handlers.keys().copied().collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+// identifies a route registered via `Router::register` across later changes to its path
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+pub struct RouteHandle(usize);
+
+// mutable access to a registered route, limited to what doesn't affect where it is filed:
+// its verb, path and domain can only change through `Router::rename_by_handle`
+pub struct RouteMut<'a> {
+    route: &'a mut Route,
+}
+
+impl<'a> RouteMut<'a> {
+    pub fn route(&self) -> &Route {
+        self.route
+    }
+
+    pub fn set_meta(&mut self, key: &str, value: &str) {
+        self.route
+            .metadata
+            .insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove_meta(&mut self, key: &str) -> Option<String> {
+        self.route.metadata.remove(key)
+    }
+
+    pub fn set_summary(&mut self, summary: &str) {
+        self.set_meta("summary", summary);
+    }
+
+    pub fn set_description(&mut self, description: &str) {
+        self.set_meta("description", description);
+    }
+
+    pub fn insert_ext<T: Any + Send + Sync>(&mut self, value: T) {
+        self.route.insert_ext(value);
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct VerificationReport {
+    pub missing: Vec<&'static str>,
+    pub unused: Vec<&'static str>,
+}
+
+impl VerificationReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.unused.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct CoverageReport {
+    pub hit: Vec<(Verb, String)>,
+    pub unhit: Vec<(Verb, String)>,
+    pub unmatched: Vec<String>,
+}
+
+fn keys_overlap(a: &RouteKey, b: &RouteKey) -> bool {
+    a.verb == b.verb
+        && a.domain == b.domain
+        && a.domain_set == b.domain_set
+        && a.query == b.query
+        && a.parts.len() == b.parts.len()
+        && a.parts
+            .iter()
+            .zip(b.parts.iter())
+            .all(|(a, b)| parts_overlap(a, b))
+}
+
+// the route store: a tree with one level per path segment, where each node has a child per
+// literal segment and per kind of typed slot, and routes live at the node their last part
+// leads to. Lookups only descend into branches the incoming path could match, so their cost
+// tracks path depth rather than the total number of routes.
+#[derive(Clone, Default)]
+struct RouteNode {
+    literals: HashMap<String, RouteNode>,
+    slots: Vec<(RoutePart, RouteNode)>,
+    routes: Vec<(RouteKey, Route)>,
+}
+
+impl RouteNode {
+    fn node(&self, parts: &[RoutePart]) -> Option<&RouteNode> {
+        match parts.split_first() {
+            None => Some(self),
+            Some((RoutePart::Path(literal), rest)) => self.literals.get(literal)?.node(rest),
+            Some((part, rest)) => self
+                .slots
+                .iter()
+                .find(|(slot, _)| slot == part)
+                .and_then(|(_, child)| child.node(rest)),
+        }
+    }
+
+    fn node_mut(&mut self, parts: &[RoutePart]) -> &mut RouteNode {
+        match parts.split_first() {
+            None => self,
+            Some((RoutePart::Path(literal), rest)) => self
+                .literals
+                .entry(literal.clone())
+                .or_default()
+                .node_mut(rest),
+            Some((part, rest)) => {
+                let index = match self.slots.iter().position(|(slot, _)| slot == part) {
+                    Some(index) => index,
+                    None => {
+                        self.slots.push((part.clone(), RouteNode::default()));
+                        self.slots.len() - 1
+                    }
+                };
+                self.slots[index].1.node_mut(rest)
+            }
+        }
+    }
+
+    fn get(&self, key: &RouteKey) -> Option<&Route> {
+        self.node(&key.parts)?
+            .routes
+            .iter()
+            .find(|(existing, _)| existing == key)
+            .map(|(_, route)| route)
+    }
+
+    fn get_mut(&mut self, key: &RouteKey) -> Option<&mut Route> {
+        let mut node = self;
+        for part in key.parts.iter() {
+            node = match part {
+                RoutePart::Path(literal) => node.literals.get_mut(literal)?,
+                part => &mut node.slots.iter_mut().find(|(slot, _)| slot == part)?.1,
+            };
+        }
+        node.routes
+            .iter_mut()
+            .find(|(existing, _)| existing == key)
+            .map(|(_, route)| route)
+    }
+
+    fn contains_key(&self, key: &RouteKey) -> bool {
+        self.get(key).is_some()
+    }
+
+    // removes the route stored under `key`, pruning branches left without routes
+    fn remove(&mut self, key: &RouteKey) -> Option<Route> {
+        self.remove_at(&key.parts, key)
+    }
+
+    fn remove_at(&mut self, parts: &[RoutePart], key: &RouteKey) -> Option<Route> {
+        match parts.split_first() {
+            None => {
+                let index = self
+                    .routes
+                    .iter()
+                    .position(|(existing, _)| existing == key)?;
+                Some(self.routes.remove(index).1)
+            }
+            Some((RoutePart::Path(literal), rest)) => {
+                let child = self.literals.get_mut(literal)?;
+                let removed = child.remove_at(rest, key);
+                if child.is_empty() {
+                    self.literals.remove(literal);
+                }
+                removed
+            }
+            Some((part, rest)) => {
+                let index = self.slots.iter().position(|(slot, _)| slot == part)?;
+                let removed = self.slots[index].1.remove_at(rest, key);
+                if self.slots[index].1.is_empty() {
+                    self.slots.remove(index);
+                }
+                removed
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.routes.is_empty() && self.literals.is_empty() && self.slots.is_empty()
+    }
+
+    fn insert(&mut self, key: RouteKey, route: Route) {
+        let node = self.node_mut(&key.parts);
+        match node
+            .routes
+            .iter_mut()
+            .find(|(existing, _)| *existing == key)
+        {
+            Some(existing) => existing.1 = route,
+            None => node.routes.push((key, route)),
+        }
+    }
+
+    fn iter(&self) -> std::vec::IntoIter<(&RouteKey, &Route)> {
+        let mut routes = Vec::new();
+        self.collect(&mut routes);
+        routes.into_iter()
+    }
+
+    fn collect<'a>(&'a self, out: &mut Vec<(&'a RouteKey, &'a Route)>) {
+        out.extend(self.routes.iter().map(|(key, route)| (key, route)));
+        for child in self.literals.values() {
+            child.collect(out);
+        }
+        for (_, child) in self.slots.iter() {
+            child.collect(out);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.routes.len()
+            + self.literals.values().map(RouteNode::len).sum::<usize>()
+            + self
+                .slots
+                .iter()
+                .map(|(_, child)| child.len())
+                .sum::<usize>()
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &RouteKey> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Route> {
+        self.iter().map(|(_, route)| route)
+    }
+
+    fn values_mut(&mut self) -> Vec<&mut Route> {
+        let mut routes: Vec<&mut Route> = self.routes.iter_mut().map(|(_, route)| route).collect();
+        for child in self.literals.values_mut() {
+            routes.extend(child.values_mut());
+        }
+        for (_, child) in self.slots.iter_mut() {
+            routes.extend(child.values_mut());
+        }
+        routes
+    }
+
+    fn literal_children(&self, token: &str, case_insensitive: bool) -> Vec<&RouteNode> {
+        if !case_insensitive {
+            return self.literals.get(token).into_iter().collect();
+        }
+        self.literals
+            .iter()
+            .filter(|(literal, _)| literal_eq(literal, token, true))
+            .map(|(_, child)| child)
+            .collect()
+    }
+
+    // every route whose shape could match `segments`. This only prunes on literals and
+    // segment counts, so candidates still have to be checked with `Route::capture`; a route
+    // may also be listed more than once.
+    fn candidates<'a>(
+        &'a self,
+        segments: &[&str],
+        case_insensitive: bool,
+        out: &mut Vec<(&'a RouteKey, &'a Route)>,
+    ) {
+        for (part, child) in self.slots.iter() {
+            match part {
+                RoutePart::Repeat | RoutePart::Wildcard => {
+                    out.extend(child.routes.iter().map(|(key, route)| (key, route)))
+                }
+                _ => {
+                    if let Some((_, rest)) = segments.split_first() {
+                        child.candidates(rest, case_insensitive, out);
+                    }
+                    // dates may also span three `YYYY/MM/DD` segments
+                    if let (RoutePart::Date, Some(rest)) = (part, segments.get(3..)) {
+                        child.candidates(rest, case_insensitive, out);
+                    }
+                }
+            }
+        }
+        match segments.split_first() {
+            None => {
+                out.extend(self.routes.iter().map(|(key, route)| (key, route)));
+                // routes ending in an optional var also match when it's left out
+                for (_, child) in self.slots.iter() {
+                    out.extend(
+                        child
+                            .routes
+                            .iter()
+                            .filter(|(_, route)| route.optional)
+                            .map(|(key, route)| (key, route)),
+                    );
+                }
+            }
+            Some((first, rest)) => {
+                for child in self.literal_children(first, case_insensitive) {
+                    child.candidates(rest, case_insensitive, out);
+                }
+                // routes with a format see the final segment without its extension
+                if let (Some((stem, _)), true) = (first.rsplit_once('.'), rest.is_empty()) {
+                    for child in self.literal_children(stem, case_insensitive) {
+                        child.candidates(rest, case_insensitive, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// how many threads run the handlers of routes with a timeout, and how many such requests may
+// wait for one; requests past that are answered as timed out straight away
+pub const TIMEOUT_WORKERS: usize = 8;
+pub const TIMEOUT_QUEUE: usize = 64;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// the threads timed handlers run on. A handler that overruns its timeout can't be interrupted
+// and keeps its worker busy until it returns, so the pool being fixed in size is what stops
+// slow handlers from piling up threads.
+struct TimeoutPool {
+    jobs: mpsc::SyncSender<Job>,
+}
+
+impl TimeoutPool {
+    fn new() -> Arc<TimeoutPool> {
+        let (jobs, receiver) = mpsc::sync_channel::<Job>(TIMEOUT_QUEUE);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..TIMEOUT_WORKERS {
+            let receiver = receiver.clone();
+            // workers exit once the router, and with it the sending half, is dropped
+            thread::spawn(move || loop {
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                // a panicking handler only loses its own response, not the worker
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            });
+        }
+        Arc::new(TimeoutPool { jobs })
+    }
+}
+
+#[derive(Clone)]
+pub struct Router {
+    routes: RouteNode,
+    svar_routes: Vec<(RouteKey, Route)>,
+    handles: HashMap<RouteHandle, RouteKey>,
+    fallback: Option<Endpoint>,
+    fallback_guard: bool,
+    reject_conflicts: bool,
+    host_optional: bool,
+    matrix_params: bool,
+    canonical_redirect: bool,
+    json_errors: bool,
+    strip_prefix: Option<&'static str>,
+    prefix_required: bool,
+    inject_full_path: bool,
+    timeout_handler: Option<Endpoint>,
+    max_body: Option<usize>,
+    too_large_handler: Option<Endpoint>,
+    domain_regexes: OnceLock<DomainRegexes>,
+    // started by the first request to a route with a timeout
+    timeout_pool: OnceLock<Arc<TimeoutPool>>,
+    domain_validator: Option<fn(&str) -> bool>,
+    middleware: Vec<(Scope, Middleware)>,
+    default_headers: Vec<(String, String)>,
+    case_insensitive: bool,
+    seq: usize,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: RouteNode::default(),
+            svar_routes: Vec::new(),
+            handles: HashMap::new(),
+            fallback: None,
+            fallback_guard: false,
+            reject_conflicts: false,
+            host_optional: false,
+            matrix_params: false,
+            canonical_redirect: false,
+            json_errors: false,
+            strip_prefix: None,
+            prefix_required: true,
+            inject_full_path: false,
+            timeout_handler: None,
+            max_body: None,
+            too_large_handler: None,
+            domain_regexes: OnceLock::new(),
+            timeout_pool: OnceLock::new(),
+            domain_validator: None,
+            middleware: Vec::new(),
+            default_headers: Vec::new(),
+            case_insensitive: false,
+            seq: 0,
+        }
+    }
+
+    pub fn set_fallback(&mut self, target: Endpoint) {
+        self.fallback = Some(target);
+    }
+
+    pub fn fallback(&self) -> Option<Endpoint> {
+        self.fallback
+    }
+
+    // when enabled, `route` rejects domainless catch-alls at the root (i.e. `/*path`),
+    // since these would match every request and the fallback could never be reached
+    pub fn set_fallback_guard(&mut self, enabled: bool) {
+        self.fallback_guard = enabled;
+    }
+
+    // when enabled, `route` rejects routes that could match the same requests as an existing
+    // one (see `check_conflicts`), instead of leaving precedence to decide between them
+    pub fn set_reject_conflicts(&mut self, enabled: bool) {
+        self.reject_conflicts = enabled;
+    }
+
+    // when enabled, requests without a host also match routes registered for a specific
+    // domain (the first registered wins) instead of only domainless routes
+    pub fn set_host_optional(&mut self, enabled: bool) {
+        self.host_optional = enabled;
+    }
+
+    // when enabled, a request that only matches once normalized (literals differing in case
+    // under `set_case_insensitive`, or trailing and doubled slashes) is answered with a
+    // permanent redirect to the canonical path instead of being served. Not applied when
+    // matrix params are enabled.
+    pub fn set_canonical_redirect(&mut self, enabled: bool) {
+        self.canonical_redirect = enabled;
+    }
+
+    // when enabled, `;key=value` matrix params trailing any segment of an incoming path (as in
+    // `/users;role=admin/5`) are stripped before matching and surfaced as string url params.
+    // This only concerns incoming paths; a `;` leading a segment of a route definition still
+    // declares a float var.
+    pub fn set_matrix_params(&mut self, enabled: bool) {
+        self.matrix_params = enabled;
+    }
+
+    // when enabled, errors returned by fallible endpoints are rendered as `{"error": ...}`
+    // json bodies rather than plain text
+    pub fn set_json_errors(&mut self, enabled: bool) {
+        self.json_errors = enabled;
+    }
+
+    // strips `prefix` (e.g. `/myapp` added by a reverse proxy) from incoming paths before
+    // matching. Paths lacking the prefix match nothing unless `set_prefix_required(false)`.
+    pub fn set_strip_prefix(&mut self, prefix: &'static str) {
+        self.strip_prefix = Some(prefix.trim_end_matches('/')).filter(|p| !p.is_empty());
+    }
+
+    pub fn set_prefix_required(&mut self, required: bool) {
+        self.prefix_required = required;
+    }
+
+    // when enabled, every match also carries the request path exactly as it came in (before
+    // prefix stripping and matrix param extraction, but without the query) as the reserved
+    // `__path` url param. Routes may never declare a var by that name.
+    pub fn set_inject_full_path(&mut self, enabled: bool) {
+        self.inject_full_path = enabled;
+    }
+
+    // rendered in place of a route that exceeded its timeout; defaults to a plain 504
+    pub fn set_timeout_handler(&mut self, target: Endpoint) {
+        self.timeout_handler = Some(target);
+    }
+
+    // when enabled, literal segments match regardless of case (`/Contact` matches `/contact`),
+    // while captured values keep the case they came in with
+    pub fn set_case_insensitive(&mut self, enabled: bool) {
+        self.case_insensitive = enabled;
+        for route in self.routes.values_mut() {
+            route.case_insensitive = enabled;
+        }
+    }
+
+    // headers added to every dispatched response, e.g. security headers; route and handler
+    // headers of the same name take precedence
+    pub fn set_default_headers(&mut self, headers: Vec<(String, String)>) {
+        self.default_headers = headers;
+    }
+
+    // runs `middleware` ahead of dispatching to any route within `scope`; middleware runs in
+    // the order it was added
+    pub fn use_middleware_for(&mut self, scope: Scope, middleware: Middleware) {
+        self.middleware.push((scope, middleware));
+    }
+
+    // replaces the default domain grammar when validating the domains routes are registered
+    // under; `validator` receives the lowercased domain
+    pub fn set_domain_validator(&mut self, validator: fn(&str) -> bool) {
+        self.domain_validator = Some(validator);
+    }
+
+    // whether the default domain grammar has been compiled yet, which only happens once a
+    // route with a domain is registered
+    pub fn domain_regexes_compiled(&self) -> bool {
+        self.domain_regexes.get().is_some()
+    }
+
+    // the body size limit for routes that don't set their own via `RouteBuilder::max_body`
+    pub fn set_max_body(&mut self, bytes: usize) {
+        self.max_body = Some(bytes);
+    }
+
+    // rendered in place of a route whose body limit was exceeded; defaults to a plain 413
+    pub fn set_too_large_handler(&mut self, target: Endpoint) {
+        self.too_large_handler = Some(target);
+    }
+
+    pub fn find(&self, verb: Verb, path: &str, domain: Option<&str>) -> Option<Endpoint> {
+        self.find_route(verb, path, domain)
+            .map(|m| m.target_for(path))
+    }
+
+    // like `find`, but tells a malformed request (`Err`, i.e. a 400) apart from one that is
+    // well-formed but matches no route (`Ok(None)`, i.e. a 404)
+    pub fn try_find(
+        &self,
+        verb: Verb,
+        path: &str,
+        domain: Option<&str>,
+    ) -> Result<Option<Endpoint>, RouteError> {
+        let (path_only, _) = split_query(path);
+        if !path_only.starts_with('/')
+            || path_only
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(RouteError::InvalidPathFormat);
+        }
+        if let Some(host) = domain {
+            let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == ':';
+            if host.is_empty() || !host.chars().all(valid) {
+                return Err(RouteError::InvalidDomain);
+            }
+        }
+        Ok(self.find(verb, path, domain))
+    }
+
+    // every verb with a route matching `path` on `domain`, e.g. for an `Allow` header
+    pub fn find_allowed_methods(&self, path: &str, domain: Option<&str>) -> Vec<Verb> {
+        let (path, query) = split_query(path);
+        let query = VerbParams::from_query(query);
+        let decoded = match self.segments(path) {
+            Some((segments, _)) => segments,
+            None => return Vec::new(),
+        };
+        let segments: Vec<&str> = decoded.iter().map(|segment| segment.as_ref()).collect();
+        let mut candidates = Vec::new();
+        self.routes
+            .candidates(&segments, self.case_insensitive, &mut candidates);
+        let mut verbs: Vec<Verb> = candidates
+            .into_iter()
+            .filter(|(_, route)| {
+                route.matches_domain(domain, self.host_optional)
+                    && route.accepts_query(&query)
+                    && route.capture(&segments).is_some()
+            })
+            .map(|(_, route)| route.verb.clone())
+            .collect();
+        // GET routes also serve HEAD requests
+        if verbs.contains(&Verb::Get) {
+            verbs.push(Verb::Head);
+        }
+        verbs.sort();
+        verbs.dedup();
+        verbs
+    }
+
+    pub fn find_or_405(&self, verb: Verb, path: &str, domain: Option<&str>) -> FindResult {
+        if let Some(target) = self.find(verb, path, domain) {
+            return FindResult::Found(target);
+        }
+        match self.find_allowed_methods(path, domain) {
+            verbs if verbs.is_empty() => FindResult::NotFound,
+            verbs => FindResult::MethodNotAllowed(verbs),
+        }
+    }
+
+    pub fn find_with_params(
+        &self,
+        verb: Verb,
+        path: &str,
+        domain: Option<&str>,
+    ) -> Option<(Endpoint, UrlParams)> {
+        self.find_route(verb, path, domain)
+            .map(|m| (m.target_for(path), m.into_params()))
+    }
+
+    pub fn find_route(
+        &self,
+        verb: Verb,
+        path: &str,
+        domain: Option<&str>,
+    ) -> Option<RouteMatch<'_>> {
+        let (path, query) = split_query(path);
+        self.match_route(verb, path, &VerbParams::from_query(query), domain)
+    }
+
+    pub fn dispatch(&self, verb: Verb, path: &str, domain: Option<&str>) -> Render {
+        self.dispatch_with_body(verb, path, domain, &[])
+    }
+
+    // like `dispatch`, but with a url-encoded request body that becomes the post params
+    pub fn dispatch_with_body(
+        &self,
+        verb: Verb,
+        path: &str,
+        domain: Option<&str>,
+        body: &[u8],
+    ) -> Render {
+        let (path, query) = split_query(path);
+        self.dispatch_parts(verb, domain, path, query, body)
+            .unwrap_or_else(|| Render::not_found().with_default_headers(&self.default_headers))
+    }
+
+    // dispatches a request whose authority (host, optionally with a port), path and query
+    // arrive already separated, as with HTTP/2's pseudo-headers. Returns `None` when neither
+    // a route nor the fallback handles the request.
+    pub fn dispatch_parts(
+        &self,
+        verb: Verb,
+        authority: Option<&str>,
+        path: &str,
+        query: &str,
+        post: &[u8],
+    ) -> Option<Render> {
+        let domain = authority.map(host_name);
+        let get = VerbParams::from_query(query);
+        let m = self.match_route(verb, path, &get, domain);
+        self.render_match(m, path, query, get, post)
+    }
+
+    // the response to a request already run through `match_route`
+    fn render_match(
+        &self,
+        m: Option<RouteMatch>,
+        path: &str,
+        query: &str,
+        get: VerbParams,
+        post: &[u8],
+    ) -> Option<Render> {
+        let render = match m {
+            Some(m) => match self.canonical_location(&m, path, query) {
+                Some(location) => Render::Redirect {
+                    location,
+                    permanent: true,
+                },
+                None => self
+                    .dispatch_match(&m, get, post)
+                    .with_default_headers(&m.route.headers),
+            },
+            None => self.fallback?(&UrlParams::new(), &get, &VerbParams::new()),
+        };
+        Some(render.with_default_headers(&self.default_headers))
+    }
+
+    // like `dispatch_parts`, but takes the full request url, e.g.
+    // `https://example.com/users/5?tab=posts`, or just its path and query
+    pub fn dispatch_url(&self, verb: Verb, url: &str, post: &[u8]) -> Option<Render> {
+        let (authority, rest) = split_url(url);
+        let (path, query) = split_query(rest);
+        self.dispatch_parts(verb, authority, path, query, post)
+    }
+
+    // where to redirect a request for `path` matched by `m` when canonical redirects are on
+    // and `path` isn't already canonical
+    fn canonical_location(&self, m: &RouteMatch, path: &str, query: &str) -> Option<String> {
+        if !self.canonical_redirect || self.matrix_params {
+            return None;
+        }
+        let rest = self.without_prefix(path)?;
+        let segments: Vec<&str> = rest.split('/').filter(|s| !s.is_empty()).collect();
+        let mut canonical = path[..path.len() - rest.len()].to_string();
+        for segment in m.route.canonical_segments(&segments) {
+            canonical.push('/');
+            canonical.push_str(&segment);
+        }
+        if canonical.is_empty() {
+            canonical.push('/');
+        }
+        if canonical == path {
+            return None;
+        }
+        if !query.is_empty() {
+            canonical.push('?');
+            canonical.push_str(query);
+        }
+        Some(canonical)
+    }
+
+    fn dispatch_match(&self, m: &RouteMatch, mut get: VerbParams, body: &[u8]) -> Render {
+        if m.route.strict_query {
+            if let Some(key) = m.route.undeclared_query(&get) {
+                return Render::status(
+                    400,
+                    &format!("unknown query param: {}", key),
+                    "text/plain; charset=utf-8",
+                );
+            }
+        }
+        if let Some(limit) = m.route.max_body.or(self.max_body) {
+            if body.len() > limit {
+                return self.too_large(m.params(), &get);
+            }
+        }
+        let post = VerbParams::from_form(&String::from_utf8_lossy(body));
+        m.route.apply_query(&mut get);
+        for (scope, middleware) in self.middleware.iter() {
+            if scope.matches(m.route) {
+                if let Some(render) = middleware(m.params(), &get, &post) {
+                    return render;
+                }
+            }
+        }
+        self.invoke(m, &get, &post)
+    }
+
+    // dispatches the request and serializes the response to `out`, leaving out the body for
+    // HEAD requests (whose headers still describe the body the GET route would have sent)
+    pub fn respond<W: Write>(
+        &self,
+        verb: Verb,
+        path: &str,
+        domain: Option<&str>,
+        out: &mut W,
+    ) -> io::Result<()> {
+        let head = verb == Verb::Head;
+        let render = self.dispatch(verb, path, domain);
+        if head {
+            render.write_http_head(out)
+        } else {
+            render.write_http(out)
+        }
+    }
+
+    // serves HTTP/1.1 on `addr` until accepting fails, answering each connection on its own
+    // thread. Each connection carries a single request and is closed once it has been
+    // answered. Bodies are capped at `DEFAULT_MAX_BODY` unless a `max_body` applies.
+    #[cfg(feature = "server")]
+    pub fn serve(&self, addr: &str) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let stream = stream?;
+                // a client hanging up mid-request shouldn't take the server down with it
+                scope.spawn(move || {
+                    let _ = self.serve_connection(stream);
+                });
+            }
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "server")]
+    fn serve_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let (verb, render) = self.read_request(&mut reader)?;
+        match verb {
+            Some(Verb::Head) => render.write_http_head(&mut stream),
+            _ => render.write_http(&mut stream),
+        }
+    }
+
+    // reads a request off `reader` and dispatches it, along with its verb if it had a known
+    // one. Heads over `MAX_HEAD` and bodies over the applicable limit are answered without
+    // reading any further.
+    #[cfg(feature = "server")]
+    fn read_request<R: BufRead>(&self, reader: &mut R) -> io::Result<(Option<Verb>, Render)> {
+        let plain = "text/plain; charset=utf-8";
+        let mut budget = MAX_HEAD;
+        let line = match read_head_line(reader, &mut budget)? {
+            Some(line) => line,
+            None => return Ok((None, self.head_too_large())),
+        };
+        let mut request = line.split_whitespace();
+        let method = request.next().unwrap_or("");
+        let target = request.next().unwrap_or("/");
+        let mut host = None;
+        let mut content_length = 0;
+        loop {
+            let header = match read_head_line(reader, &mut budget)? {
+                Some(header) => header,
+                None => return Ok((None, self.head_too_large())),
+            };
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("host") {
+                    host = Some(value.trim().to_string());
+                } else if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let verb = match Verb::parse(method) {
+            Some(verb) => verb,
+            None => {
+                let render = Render::status(501, "Not Implemented", plain);
+                return Ok((None, render.with_default_headers(&self.default_headers)));
+            }
+        };
+        // the declared length is checked against the body limit before anything is read
+        let (path, query) = split_query(target);
+        let get = VerbParams::from_query(query);
+        let m = self.match_route(verb.clone(), path, &get, host.as_deref().map(host_name));
+        let limit = m
+            .as_ref()
+            .and_then(|m| m.route.max_body)
+            .or(self.max_body)
+            .unwrap_or(DEFAULT_MAX_BODY);
+        if content_length > limit {
+            let params = m.as_ref().map(|m| m.params().clone()).unwrap_or_default();
+            let render = self.too_large(&params, &get);
+            return Ok((
+                Some(verb),
+                render.with_default_headers(&self.default_headers),
+            ));
+        }
+        let mut body = Vec::new();
+        reader.take(content_length as u64).read_to_end(&mut body)?;
+        let render = self
+            .render_match(m, path, query, get, &body)
+            .unwrap_or_else(|| Render::not_found().with_default_headers(&self.default_headers));
+        Ok((Some(verb), render))
+    }
+
+    #[cfg(feature = "server")]
+    fn head_too_large(&self) -> Render {
+        Render::status(
+            431,
+            "Request Header Fields Too Large",
+            "text/plain; charset=utf-8",
+        )
+        .with_default_headers(&self.default_headers)
+    }
+
+    // answers a request whose body is over the applicable `max_body`
+    fn too_large(&self, params: &UrlParams, get: &VerbParams) -> Render {
+        match self.too_large_handler {
+            Some(handler) => handler(params, get, &VerbParams::new()),
+            None => Render::status(413, "Payload Too Large", "text/plain; charset=utf-8"),
+        }
+    }
+
+    // the route matching the longest leading run of `path`'s segments (for any verb), e.g.
+    // `/a/b` for `/a/b/c` when only `/a/b` is registered
+    pub fn longest_prefix(&self, path: &str, domain: Option<&str>) -> Option<&Route> {
+        let (decoded, _) = self.segments(split_query(path).0)?;
+        let segments: Vec<&str> = decoded.iter().map(|segment| segment.as_ref()).collect();
+        for depth in (0..=segments.len()).rev() {
+            let prefix = &segments[..depth];
+            let mut candidates = Vec::new();
+            self.routes
+                .candidates(prefix, self.case_insensitive, &mut candidates);
+            let best = candidates
+                .into_iter()
+                .map(|(_, route)| route)
+                .filter(|route| route.matches_domain(domain, self.host_optional))
+                .filter(|route| route.capture(prefix).is_some())
+                .min_by(|a, b| a.precedence(b, domain));
+            if best.is_some() {
+                return best;
+            }
+        }
+        None
+    }
+
+    fn without_prefix<'p>(&self, path: &'p str) -> Option<&'p str> {
+        let prefix = match self.strip_prefix {
+            Some(prefix) => prefix,
+            None => return Some(path),
+        };
+        match path.strip_prefix(prefix) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => Some(rest),
+            _ if self.prefix_required => None,
+            _ => Some(path),
+        }
     }
-}
 
-#[derive(Clone)]
-pub struct Router {
-    routes: HashMap<RouteKey, Route>,
-    svar_routes: Vec<(RouteKey, Route)>,
-}
+    fn invoke(&self, m: &RouteMatch, get: &VerbParams, post: &VerbParams) -> Render {
+        let timeout = match m.route.timeout {
+            Some(timeout) => timeout,
+            None => return m.route.call(m.params(), get, post, self.json_errors),
+        };
+        // endpoints can't be interrupted, so a timed out handler keeps running on its worker
+        // and its eventual result is discarded
+        let (sender, receiver) = mpsc::channel();
+        let route = m.route.clone();
+        let (params, get, post) = (m.params().clone(), get.clone(), post.clone());
+        let json_errors = self.json_errors;
+        let job: Job = Box::new(move || {
+            let _ = sender.send(route.call(&params, &get, &post, json_errors));
+        });
+        let pool = self.timeout_pool.get_or_init(TimeoutPool::new);
+        if pool.jobs.try_send(job).is_err() {
+            return self.timed_out(m);
+        }
+        match receiver.recv_timeout(timeout) {
+            Ok(render) => render,
+            Err(_) => self.timed_out(m),
+        }
+    }
 
-impl Router {
-    pub fn new() -> Router {
-        Router {
-            routes: HashMap::new(),
-            svar_routes: Vec::new(),
+    fn timed_out(&self, m: &RouteMatch) -> Render {
+        match self.timeout_handler {
+            Some(handler) => handler(m.params(), &VerbParams::new(), &VerbParams::new()),
+            None => Render::status(504, "Gateway Timeout", "text/plain; charset=utf-8"),
         }
     }
 
-    pub fn find(&self, verb: Verb, path: &String, domain: Option<&String>) -> Option<Endpoint> {
-        let key = match RouteKey::new(verb, path, domain) {
-            Ok(k) => k,
-            _ => return None,
-        };
-        match self.routes.get(&key) {
-            Some(route) => Some(route.target),
-            None => {
-                for route in self.svar_routes.iter() {
-                    // TODO: actually make a regex for each one and store that in tuple
-                    // then check all regexes in this loop
-                    // \A(?:aa)\z|\A(?:bb)\z|\A(?:cc)\z
+    fn match_route(
+        &self,
+        verb: Verb,
+        path: &str,
+        query: &VerbParams,
+        domain: Option<&str>,
+    ) -> Option<RouteMatch<'_>> {
+        let full_path = path;
+        let (decoded, matrix) = self.segments(path)?;
+        let segments: Vec<&str> = decoded.iter().map(|segment| segment.as_ref()).collect();
+        let mut candidates = Vec::new();
+        self.routes
+            .candidates(&segments, self.case_insensitive, &mut candidates);
+        let mut best: Option<RouteMatch> = None;
+        for (key, route) in candidates {
+            if route.verb != verb
+                || !route.matches_domain(domain, self.host_optional)
+                || !route.accepts_query(query)
+            {
+                continue;
+            }
+            if let Some(best) = &best {
+                if best.route.precedence(route, domain) != Ordering::Greater {
+                    continue;
+                }
+            }
+            if let Some(params) = route.capture(&segments) {
+                best = Some(RouteMatch { key, route, params });
+            }
+        }
+        // HEAD requests are served by the GET route when no HEAD route matches
+        if best.is_none() && verb == Verb::Head {
+            return self.match_route(Verb::Get, full_path, query, domain);
+        }
+        if let Some(best) = &mut best {
+            if self.inject_full_path {
+                best.params.add_raw(
+                    FULL_PATH_PARAM,
+                    UrlParam::String(full_path.to_string()),
+                    full_path,
+                );
+            }
+            for (key, value) in matrix {
+                if best.params.get(key).is_none() {
+                    best.params
+                        .add_raw(key, UrlParam::String(value.to_string()), value);
+                }
+            }
+        }
+        best
+    }
+
+    // splits an incoming path (sans query) into the segments routes are matched against,
+    // along with any matrix params that were stripped from them
+    fn segments<'p>(&self, path: &'p str) -> Option<(Vec<Cow<'p, str>>, MatrixParams<'p>)> {
+        let path = self.without_prefix(path)?;
+        let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut matrix = Vec::new();
+        if self.matrix_params {
+            for segment in segments.iter_mut() {
+                if let Some((plain, params)) = segment.split_once(';') {
+                    *segment = plain;
+                    for param in params.split(';').filter(|param| !param.is_empty()) {
+                        matrix.push(param.split_once('=').unwrap_or((param, "")));
+                    }
                 }
-                None
             }
         }
+        let segments = segments.into_iter().map(percent_decode).collect();
+        Some((segments, matrix))
+    }
+
+    // every route that would accept the request, best first, i.e. the order in which
+    // matching considers them
+    pub fn resolution_order(&self, path: &str, verb: Verb, domain: Option<&str>) -> Vec<&Route> {
+        let (path, query) = split_query(path);
+        let query = VerbParams::from_query(query);
+        let decoded = match self.segments(path) {
+            Some((segments, _)) => segments,
+            None => return Vec::new(),
+        };
+        let segments: Vec<&str> = decoded.iter().map(|segment| segment.as_ref()).collect();
+        let mut routes: Vec<&Route> = self
+            .routes
+            .values()
+            .filter(|route| {
+                route.verb == verb
+                    && route.matches_domain(domain, self.host_optional)
+                    && route.accepts_query(&query)
+                    && route.capture(&segments).is_some()
+            })
+            .collect();
+        routes.sort_by(|a, b| a.precedence(b, domain));
+        routes
+    }
+
+    // the path of the earliest registered route with this name (see `Route::url_for`)
+    pub fn url_for(&self, name: &str, params: &UrlParams) -> Option<String> {
+        self.routes
+            .values()
+            .filter(|route| route.name() == Some(name))
+            .min_by_key(|route| route.seq)?
+            .url_for(params)
+    }
+
+    // routes registered through the group get `prefix` prepended; like `mount`, the prefix
+    // may only contain literal segments
+    pub fn group(&mut self, prefix: &'static str) -> RouteGroup<'_> {
+        RouteGroup {
+            prefix,
+            domain: None,
+            router: self,
+        }
     }
 
-    pub fn path(&mut self, path: &'static str) -> RouteBuilder {
+    pub fn path(&mut self, path: &'static str) -> RouteBuilder<'_> {
+        self.on(path).get()
+    }
+
+    /// Like `path`, but without defaulting to GET: the builder won't `route` until a verb has
+    /// been chosen.
+    ///
+    /// ```compile_fail
+    /// use bolts::router::*;
+    ///
+    /// fn target(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    ///     Render::Plain("hello".to_string())
+    /// }
+    ///
+    /// let mut router = Router::new();
+    /// router.on("/hello").route(target).unwrap();
+    /// ```
+    pub fn on(&mut self, path: &'static str) -> RouteBuilder<'_, Unverbed> {
         RouteBuilder {
             domain: None,
-            verb: Verb::Get,
-            path: path,
+            domains: Vec::new(),
+            verbs: vec![Verb::Get],
+            prefix: None,
+            path,
+            format: false,
+            formats: Vec::new(),
+            split_wildcard: false,
+            list_delimiter: ',',
+            empty_lists: false,
+            date_segments: false,
+            strict_query: false,
+            timeout: None,
+            max_body: None,
+            priority: 0,
+            tags: Vec::new(),
+            headers: Vec::new(),
+            metadata: HashMap::new(),
+            extensions: HashMap::new(),
             router: self,
+            state: PhantomData,
+        }
+    }
+
+    pub fn routes(&self) -> std::vec::IntoIter<&Route> {
+        self.routes.values().collect::<Vec<&Route>>().into_iter()
+    }
+
+    pub fn fold<B>(&self, init: B, mut f: impl FnMut(B, &RouteKey, &Route) -> B) -> B {
+        self.routes
+            .iter()
+            .fold(init, |acc, (key, route)| f(acc, key, route))
+    }
+
+    // the routes a request with the given verb and host could reach, in registration order
+    pub fn routes_for(&self, verb: &Verb, domain: Option<&str>) -> Vec<&Route> {
+        let mut routes: Vec<&Route> = self
+            .routes
+            .values()
+            .filter(|route| route.verb == *verb && route.matches_domain(domain, self.host_optional))
+            .collect();
+        routes.sort_by_key(|route| route.seq);
+        routes
+    }
+
+    // the verbs registered for each distinct path shape (ignoring domains and query specs),
+    // showing which paths are multiplexed across methods
+    pub fn path_shapes(&self) -> HashMap<Vec<RoutePart>, Vec<Verb>> {
+        let mut shapes: HashMap<Vec<RoutePart>, Vec<Verb>> = HashMap::new();
+        for key in self.routes.keys() {
+            let verbs = shapes.entry(key.parts.clone()).or_default();
+            if !verbs.contains(&key.verb) {
+                verbs.push(key.verb.clone());
+            }
+        }
+        for verbs in shapes.values_mut() {
+            verbs.sort();
+        }
+        shapes
+    }
+
+    // checks that every route's `example_path` (requested on one of its domains) resolves back
+    // to that same route, returning the keys of those that don't
+    pub fn self_check(&self) -> Result<(), Vec<RouteKey>> {
+        let mut routes: Vec<(&RouteKey, &Route)> = self.routes.iter().collect();
+        routes.sort_by_key(|(_, route)| route.seq);
+        let failures: Vec<RouteKey> = routes
+            .into_iter()
+            .filter(|(key, route)| {
+                // wildcard domains are requested on a sample subdomain
+                let host = route.domains().first().map(|dom| {
+                    match dom.strip_prefix("**.").or_else(|| dom.strip_prefix("*.")) {
+                        Some(suffix) => format!("www.{}", suffix),
+                        None => dom.to_string(),
+                    }
+                });
+                let path = route.example_path();
+                match self.find_route(route.verb.clone(), &path, host.as_deref()) {
+                    Some(m) => m.key() != *key,
+                    None => true,
+                }
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+
+    // pairs of routes (earlier registration first) for the same verb, domain and query spec
+    // whose paths have the same length and overlap at every position, i.e. requests that both
+    // could serve and that only precedence decides between
+    pub fn check_conflicts(&self) -> Vec<(RouteKey, RouteKey)> {
+        let mut routes: Vec<(&RouteKey, &Route)> = self.routes.iter().collect();
+        routes.sort_by_key(|(_, route)| route.seq);
+        let mut conflicts = Vec::new();
+        for (i, (a, _)) in routes.iter().enumerate() {
+            for (b, _) in routes[i + 1..].iter() {
+                if keys_overlap(a, b) {
+                    conflicts.push(((*a).clone(), (*b).clone()));
+                }
+            }
+        }
+        conflicts
+    }
+
+    pub fn has_verb(&self, verb: &Verb) -> bool {
+        self.routes.keys().any(|key| key.verb == *verb)
+    }
+
+    pub fn export_pairs(&self) -> Vec<(Verb, String)> {
+        let mut pairs: Vec<(Verb, String)> = self
+            .routes
+            .values()
+            .map(|route| (route.verb.clone(), route.to_path_string()))
+            .collect();
+        pairs.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        pairs
+    }
+
+    // compares the handler names routes were registered against (via `route_to`) with the
+    // given registry, reporting names it lacks and names no route references
+    pub fn verify_against(&self, registry: &HandlerRegistry) -> VerificationReport {
+        let referenced: HashSet<&'static str> = self
+            .routes
+            .values()
+            .filter_map(|route| route.handler)
+            .collect();
+        let mut report = VerificationReport::default();
+        for name in referenced.iter() {
+            if registry.get(name).is_none() {
+                report.missing.push(name);
+            }
+        }
+        report.missing.sort_unstable();
+        report.unused = registry
+            .names()
+            .into_iter()
+            .filter(|name| !referenced.contains(name))
+            .collect();
+        report
+    }
+
+    // runs each sample through matching for every verb, reporting which routes were hit,
+    // which were never hit, and which samples matched nothing. Samples are paths or, to reach
+    // domain routes, full urls such as `https://example.com/users/5`.
+    pub fn coverage(&self, samples: &[&str]) -> CoverageReport {
+        let mut hit = HashSet::new();
+        let mut report = CoverageReport::default();
+        for sample in samples {
+            let (authority, rest) = split_url(sample);
+            let mut matched = false;
+            for verb in Verb::all() {
+                if let Some(m) = self.find_route(verb, rest, authority.map(host_name)) {
+                    hit.insert(m.key());
+                    matched = true;
+                }
+            }
+            if !matched {
+                report.unmatched.push(sample.to_string());
+            }
+        }
+        let mut routes: Vec<(&RouteKey, (Verb, String))> = self
+            .routes
+            .iter()
+            .map(|(key, route)| (key, (route.verb.clone(), route.to_path_string())))
+            .collect();
+        routes.sort_by(|a, b| (a.1).1.cmp(&(b.1).1).then((a.1).0.cmp(&(b.1).0)));
+        for (key, pair) in routes {
+            if hit.contains(key) {
+                report.hit.push(pair);
+            } else {
+                report.unhit.push(pair);
+            }
+        }
+        report
+    }
+
+    // fully-qualified urls for every static GET route, for sitemaps. Domainless routes are
+    // prefixed with `base`, domain routes with the domain itself (using `base`'s scheme), and
+    // wildcard domain routes are skipped since they have no single url.
+    pub fn static_get_urls(&self, base: &str) -> Vec<String> {
+        let base = base.trim_end_matches('/');
+        let scheme = base.split_once("://").map_or("https", |(scheme, _)| scheme);
+        let mut urls: Vec<String> = self
+            .routes
+            .values()
+            .filter(|route| route.verb == Verb::Get && route.is_static())
+            .flat_map(|route| match route.domains() {
+                domains if domains.is_empty() => {
+                    vec![format!("{}{}", base, route.to_path_string())]
+                }
+                domains => domains
+                    .into_iter()
+                    .filter(|dom| !dom.starts_with('*'))
+                    .map(|dom| format!("{}://{}{}", scheme, dom, route.to_path_string()))
+                    .collect(),
+            })
+            .collect();
+        urls.sort_unstable();
+        urls
+    }
+
+    // lints route definitions against the params their handlers actually read. `usage` maps a
+    // handler name (for routes registered via `route_to`) or a route template such as
+    // `/users/:id` to the params read; routes with no entry are skipped. Returns every
+    // declared var that is never read.
+    pub fn unused_params(&self, usage: &HashMap<&str, Vec<&str>>) -> Vec<(RouteKey, String)> {
+        let mut routes: Vec<(&RouteKey, &Route)> = self.routes.iter().collect();
+        routes.sort_by_key(|(_, route)| route.seq);
+        let mut unused = Vec::new();
+        for (key, route) in routes {
+            let read = match route.handler.and_then(|name| usage.get(name)) {
+                Some(read) => read,
+                None => match usage.get(route.to_path_string().as_str()) {
+                    Some(read) => read,
+                    None => continue,
+                },
+            };
+            for var in route.vars.iter() {
+                if !read.contains(&var.name()) {
+                    unused.push((key.clone(), var.name().to_string()));
+                }
+            }
         }
+        unused
+    }
+
+    // routes registered under a wildcard domain whose verb and path have no counterpart under
+    // an exact domain, i.e. routes that are only reachable via arbitrary subdomains. A route
+    // whose domain set mixes both kinds is its own exact counterpart.
+    pub fn wildcard_only_routes(&self) -> Vec<&Route> {
+        let has_wildcard = |route: &Route| route.domains().iter().any(|dom| dom.starts_with('*'));
+        let has_exact = |route: &Route| route.domains().iter().any(|dom| !dom.starts_with('*'));
+        let mut routes: Vec<&Route> = self
+            .routes
+            .values()
+            .filter(|route| has_wildcard(route))
+            .filter(|route| {
+                !self.routes.values().any(|other| {
+                    has_exact(other) && other.verb == route.verb && other.parts == route.parts
+                })
+            })
+            .collect();
+        routes.sort_by_key(|route| route.seq);
+        routes
+    }
+
+    // dumps the shape of the router (everything but the endpoints themselves) as one sorted
+    // line per route, suitable for golden-file comparisons
+    pub fn to_snapshot(&self) -> String {
+        let mut lines: Vec<String> = self.routes.values().map(Route::snapshot_line).collect();
+        lines.sort_unstable();
+        lines.join("\n")
+    }
+
+    // a JSON array describing every route in registration order, for documentation tooling
+    pub fn to_manifest(&self) -> String {
+        let json = |value: Option<&str>| value.map_or("null".to_string(), escape_json);
+        // a domain set is listed as an array of its members
+        let domain = |route: &Route| {
+            if route.domain_set.is_empty() {
+                return json(route.domain.as_deref());
+            }
+            let members: Vec<String> = route
+                .domain_set
+                .iter()
+                .map(|dom| escape_json(dom))
+                .collect();
+            format!("[{}]", members.join(","))
+        };
+        let mut routes: Vec<&Route> = self.routes.values().collect();
+        routes.sort_by_key(|route| route.seq);
+        let entries: Vec<String> = routes
+            .into_iter()
+            .map(|route| {
+                format!(
+                    "{{\"verb\":{},\"domain\":{},\"path\":{},\"summary\":{},\"description\":{}}}",
+                    escape_json(route.verb.as_str()),
+                    domain(route),
+                    escape_json(&route.to_path_string()),
+                    json(route.summary()),
+                    json(route.description())
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(","))
     }
 
-    pub fn routes(&self) -> hash_map::Values<'_, RouteKey, Route> {
-        self.routes.values()
+    pub fn snapshot_eq(&self, snapshot: &str) -> bool {
+        self.to_snapshot() == snapshot.trim()
     }
 
     pub fn string_var_routes(&self) -> &Vec<(RouteKey, Route)> {
@@ -294,66 +3786,541 @@ impl Router {
         verb: Verb,
         path: &'static str,
         target: Endpoint,
+    ) -> Result<(), RouteError> {
+        let (route_key, route) = self.parse_route(domain, verb, path, target)?;
+        self.insert(route_key, route)
+    }
+
+    // registers `target` once per verb, all or nothing
+    pub fn route_verbs(
+        &mut self,
+        verbs: &[Verb],
+        domain: Option<&'static str>,
+        path: &'static str,
+        target: Endpoint,
     ) -> Result<(), &'static str> {
-        if !PATH_REG.is_match(path) {
-            return Err("invalid route format!");
+        let mut routes = Vec::new();
+        for verb in verbs.iter() {
+            let route = self
+                .parse_route(domain, verb.clone(), path, target)
+                .map_err(|err| err.message())?;
+            routes.push(route);
         }
-        let domain = match domain {
-            Some(dom) => {
-                let dom = dom.to_lowercase();
-                if !DOM_REG_SIMPLE.is_match(&dom) && !DOM_REG_WILDCARD.is_match(&dom) {
-                    return Err("invalid domain!");
-                }
-                Some(dom.to_string())
-            }
-            None => None,
+        self.insert_all(routes).map_err(|err| err.message())
+    }
+
+    // like `route`, but returns a handle for finding, renaming or removing the route later
+    pub fn register(
+        &mut self,
+        domain: Option<&'static str>,
+        verb: Verb,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<RouteHandle, RouteError> {
+        let (route_key, route) = self.parse_route(domain, verb, path, target)?;
+        let handle = RouteHandle(self.seq);
+        self.insert(route_key.clone(), route)?;
+        self.handles.insert(handle, route_key);
+        Ok(handle)
+    }
+
+    pub fn get_by_handle(&self, handle: RouteHandle) -> Option<&Route> {
+        self.routes.get(self.handles.get(&handle)?)
+    }
+
+    pub fn get_mut_by_handle(&mut self, handle: RouteHandle) -> Option<RouteMut<'_>> {
+        let key = self.handles.get(&handle)?;
+        let route = self.routes.get_mut(key)?;
+        Some(RouteMut { route })
+    }
+
+    pub fn remove_by_handle(&mut self, handle: RouteHandle) -> Option<Route> {
+        let key = self.handles.remove(&handle)?;
+        self.remove_key(&key)
+    }
+
+    // unregisters the route declared with this verb, path and domain, returning whether one
+    // was registered. Var names don't need to match, only the shape of the path. A path
+    // without query specs also finds a route declared with some, as long as it is the only
+    // route of that shape; otherwise the specs are needed to pick one.
+    pub fn remove(&mut self, verb: Verb, path: &str, domain: Option<&str>) -> bool {
+        let key = match self.route_key(domain, verb, path) {
+            Ok(key) => key,
+            Err(_) => return false,
         };
-        let mut route_key = RouteKey {
-            domain: domain.clone(),
-            parts: Vec::new(),
-            verb: verb.clone(),
+        if self.remove_key(&key).is_some() {
+            return true;
+        }
+        if !key.query.is_empty() {
+            return false;
+        }
+        let shaped: Vec<RouteKey> = self
+            .routes
+            .keys()
+            .filter(|existing| {
+                existing.verb == key.verb
+                    && existing.domain == key.domain
+                    && existing.domain_set == key.domain_set
+                    && existing.parts == key.parts
+            })
+            .cloned()
+            .collect();
+        match shaped.as_slice() {
+            [existing] => self.remove_key(existing).is_some(),
+            _ => false,
+        }
+    }
+
+    // the number of registered routes
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    // unregisters every route, keeping the router's own settings
+    pub fn clear(&mut self) {
+        self.routes = RouteNode::default();
+        self.svar_routes.clear();
+        self.handles.clear();
+    }
+
+    // re-registers every route of `sub` beneath `prefix`, which must be made only of
+    // literal segments. Nothing is mounted if any prefixed route fails to register.
+    pub fn mount(&mut self, prefix: &'static str, sub: Router) -> Result<(), &'static str> {
+        let prefix_parts = static_prefix(prefix).map_err(|err| match err {
+            RouteError::DynamicPrefix => "mount prefix must be static",
+            err => err.message(),
+        })?;
+        let mut routes: Vec<(RouteKey, Route)> = sub
+            .routes
+            .iter()
+            .map(|(key, route)| {
+                let mut key = key.clone();
+                let mut route = route.clone();
+                key.parts = prefix_parts.iter().cloned().chain(key.parts).collect();
+                route.parts = key.parts.clone();
+                (key, route)
+            })
+            .collect();
+        routes.sort_by_key(|(_, route)| route.seq);
+        self.insert_all(routes).map_err(|err| err.message())
+    }
+
+    fn remove_key(&mut self, key: &RouteKey) -> Option<Route> {
+        self.svar_routes.retain(|(existing, _)| existing != key);
+        self.handles.retain(|_, existing| existing != key);
+        self.routes.remove(key)
+    }
+
+    // moves the route to `path`, keeping its domain, verb, endpoint, settings and place in
+    // the precedence order. On failure the route is left where it was.
+    pub fn rename_by_handle(
+        &mut self,
+        handle: RouteHandle,
+        path: &'static str,
+    ) -> Result<(), RouteError> {
+        let old_key = self
+            .handles
+            .get(&handle)
+            .cloned()
+            .ok_or(RouteError::UnknownRoute)?;
+        let route = self.routes.get(&old_key).ok_or(RouteError::UnknownRoute)?;
+        let (mut new_key, parsed) =
+            self.parse_route(None, route.verb.clone(), path, route.target)?;
+        new_key.domain = route.domain.clone();
+        new_key.domain_set = route.domain_set.clone();
+        let mut route = self
+            .remove_by_handle(handle)
+            .ok_or(RouteError::UnknownRoute)?;
+        let seq = route.seq;
+        if let Err(err) = self.check_insert(&new_key) {
+            self.store(old_key.clone(), route, seq);
+            self.handles.insert(handle, old_key);
+            return Err(err);
+        }
+        route.parts = parsed.parts;
+        route.vars = parsed.vars;
+        route.query = parsed.query;
+        route.optional = parsed.optional;
+        self.store(new_key.clone(), route, seq);
+        self.handles.insert(handle, new_key);
+        Ok(())
+    }
+
+    // like `route`, but trusts that `path` was already validated (e.g. when reloading a
+    // table that was checked on a previous load) and skips the path regex. Debug builds
+    // still validate and panic on an invalid path.
+    pub fn route_prevalidated(
+        &mut self,
+        domain: Option<&'static str>,
+        verb: Verb,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<(), RouteError> {
+        debug_assert!(
+            PATH_REG.is_match(split_route_path(path).0),
+            "route_prevalidated called with an invalid path: {}",
+            path
+        );
+        let (route_key, route) = self.build_route(domain, verb, path, target)?;
+        self.insert(route_key, route)
+    }
+
+    // registers `target` at each of `paths`, continuing past failures and returning every
+    // path that could not be registered along with its error
+    pub fn route_paths(
+        &mut self,
+        verb: Verb,
+        paths: &[&'static str],
+        target: Endpoint,
+    ) -> Result<(), Vec<(&'static str, RouteError)>> {
+        let errors: Vec<(&'static str, RouteError)> = paths
+            .iter()
+            .filter_map(|path| {
+                self.route(None, verb.clone(), path, target)
+                    .err()
+                    .map(|err| (*path, err))
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // registers an endpoint that may fail; its errors are turned into responses carrying the
+    // error's status. Such routes are served by `dispatch` and `RouteMatch::call`; the
+    // endpoint `find` hands out for them only answers 500.
+    pub fn route_fallible(
+        &mut self,
+        domain: Option<&'static str>,
+        verb: Verb,
+        path: &'static str,
+        target: FallibleEndpoint,
+    ) -> Result<(), RouteError> {
+        let (route_key, mut route) = self.parse_route(domain, verb, path, fallible_target)?;
+        route.fallible = Some(target);
+        self.insert(route_key, route)
+    }
+
+    // like `route`, but re-registering an identical route with the same target succeeds
+    // without changes, which keeps config reloads idempotent
+    pub fn ensure(
+        &mut self,
+        domain: Option<&'static str>,
+        verb: Verb,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<(), RouteError> {
+        let (route_key, route) = self.parse_route(domain, verb, path, target)?;
+        match self.routes.get(&route_key) {
+            Some(existing) if existing.target as usize == target as usize => Ok(()),
+            Some(_) => Err(RouteError::DuplicateRoute),
+            None => self.insert(route_key, route),
+        }
+    }
+
+    pub fn route_dynamic(
+        &mut self,
+        verb: Verb,
+        path: &'static str,
+        selector: Selector,
+    ) -> Result<(), RouteError> {
+        let (route_key, mut route) = self.parse_route(None, verb, path, unselected)?;
+        route.selector = Some(selector);
+        self.insert(route_key, route)
+    }
+
+    // registers `from` as another path for every route already defined at `to`, sharing its
+    // endpoint and settings; both paths must declare the same vars. Whether the last var is
+    // optional and which query params are expected come from `from` itself, like any other
+    // route path.
+    pub fn alias(&mut self, from: &'static str, to: &'static str) -> Result<(), RouteError> {
+        let (_, original) = self.parse_route(None, Verb::Get, to, unselected)?;
+        let (_, alias) = self.parse_route(None, Verb::Get, from, unselected)?;
+        let originals: Vec<Route> = self
+            .routes
+            .values()
+            .filter(|route| route.parts == original.parts && route.vars == original.vars)
+            .cloned()
+            .collect();
+        if originals.is_empty() {
+            return Err(RouteError::UnknownRoute);
+        }
+        let signature = |route: &Route| {
+            let mut vars: Vec<(&'static str, char)> = route
+                .vars
+                .iter()
+                .map(|var| (var.name(), var.sigil()))
+                .collect();
+            vars.sort_unstable();
+            vars
         };
+        if signature(&original) != signature(&alias) {
+            return Err(RouteError::IncompatibleAlias);
+        }
+        let mut aliases = Vec::new();
+        for mut route in originals {
+            route.parts = alias.parts.clone();
+            route.vars = alias.vars.clone();
+            route.optional = alias.optional;
+            route.query = alias.query.clone();
+            let route_key = RouteKey {
+                domain: route.domain.clone(),
+                domain_set: route.domain_set.clone(),
+                parts: route.parts.clone(),
+                verb: route.verb.clone(),
+                query: query_key(&route.query),
+            };
+            aliases.push((route_key, route));
+        }
+        self.insert_all(aliases)
+    }
+
+    fn parse_route(
+        &self,
+        domain: Option<&'static str>,
+        verb: Verb,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<(RouteKey, Route), RouteError> {
+        let (path_only, _, _) = split_route_path(path);
+        if !PATH_REG.is_match(path_only) {
+            return Err(path_error(path_only));
+        }
+        self.build_route(domain, verb, path, target)
+    }
+
+    // everything `parse_route` does except validating the path against `PATH_REG`
+    fn build_route(
+        &self,
+        domain: Option<&'static str>,
+        verb: Verb,
+        path: &'static str,
+        target: Endpoint,
+    ) -> Result<(RouteKey, Route), RouteError> {
+        let route_key = self.route_key(domain, verb.clone(), path)?;
+        let (path, optional, query) = split_route_path(path);
         let mut route = Route {
-            domain: domain,
+            domain: route_key.domain.clone(),
+            domain_set: Vec::new(),
+            parts: route_key.parts.clone(),
             vars: Vec::new(),
-            verb: verb,
-            target: target,
+            verb,
+            target,
+            format: false,
+            formats: Vec::new(),
+            split_wildcard: false,
+            list_delimiter: ',',
+            empty_lists: false,
+            date_segments: false,
+            query: Vec::new(),
+            strict_query: false,
+            optional: false,
+            selector: None,
+            fallible: None,
+            timeout: None,
+            max_body: None,
+            handler: None,
+            metadata: HashMap::new(),
+            extensions: HashMap::new(),
+            tags: Vec::new(),
+            headers: Vec::new(),
+            priority: 0,
+            case_insensitive: false,
+            seq: 0,
         };
-        let mut has_string_vars = false;
-        for token in path.split('/') {
-            if token.len() == 0 {
-                continue;
+        for spec in query.split('&').filter(|spec| !spec.is_empty()) {
+            route.query.push(QueryParam::parse(spec)?);
+        }
+        for token in path.split('/').filter(|token| !token.is_empty()) {
+            let var = match (constrained(token), sigil_of(token)?) {
+                (Some((name, pattern)), _) => RouteVar::Regex(name, constraint(pattern)?),
+                // every sigil is a single ascii char
+                (None, Some((_, var))) => var(&token[1..]),
+                (None, None) => continue,
+            };
+            route.vars.push(var);
+        }
+        if route.vars.iter().any(|var| var.name() == FULL_PATH_PARAM) {
+            return Err(RouteError::ReservedParam);
+        }
+        if optional {
+            // only a single-segment var in trailing position can be left out
+            match route_key.parts.last() {
+                Some(RoutePart::Path(_))
+                | Some(RoutePart::Repeat)
+                | Some(RoutePart::Wildcard)
+                | None => return Err(RouteError::InvalidPathFormat),
+                Some(_) => route.optional = true,
             }
-            match token.chars().nth(0).unwrap() {
-                ':' => {
-                    // integer var
-                    route_key.parts.push(RoutePart::Int);
-                    route.vars.push(RouteVar::Int(&token[1..]));
-                }
-                '#' => {
-                    // string var
-                    route_key.parts.push(RoutePart::String);
-                    route.vars.push(RouteVar::String(&token[1..]));
-                    has_string_vars = true;
-                }
-                ';' => {
-                    // float var
-                    route_key.parts.push(RoutePart::Float);
-                    route.vars.push(RouteVar::Float(&token[1..]));
+        }
+        Ok((route_key, route))
+    }
+
+    // the key a route declared with this domain, verb and path is filed under. Only the
+    // shape of the path matters, so this doesn't need the path to outlive the router.
+    fn route_key(
+        &self,
+        domain: Option<&str>,
+        verb: Verb,
+        path: &str,
+    ) -> Result<RouteKey, RouteError> {
+        let (path, _, query) = split_route_path(path);
+        let domain = match domain {
+            Some(dom) => Some(self.parse_domain(dom)?),
+            None => None,
+        };
+        let mut query_params = Vec::new();
+        for spec in query.split('&').filter(|spec| !spec.is_empty()) {
+            query_params.push(QueryParam::parse(spec)?);
+        }
+        let mut parts = Vec::new();
+        for token in path.split('/').filter(|token| !token.is_empty()) {
+            parts.push(route_part(token)?);
+        }
+        Ok(RouteKey {
+            domain,
+            domain_set: Vec::new(),
+            parts,
+            verb,
+            query: query_key(&query_params),
+        })
+    }
+
+    // validates with the custom validator if one was set, otherwise with the default grammar,
+    // whose regexes are compiled the first time this router sees a domain
+    fn parse_domain(&self, domain: &str) -> Result<String, RouteError> {
+        match self.domain_validator {
+            Some(validator) => parse_domain(domain, validator),
+            None => {
+                let regexes = self.domain_regexes.get_or_init(DomainRegexes::new);
+                parse_domain(domain, |dom| regexes.is_match(dom))
+            }
+        }
+    }
+
+    fn insert(&mut self, route_key: RouteKey, route: Route) -> Result<(), RouteError> {
+        self.check_insert(&route_key)?;
+        let seq = self.seq;
+        self.seq += 1;
+        self.store(route_key, route, seq);
+        Ok(())
+    }
+
+    // inserts every route or, if any would be rejected, none of them
+    fn insert_all(&mut self, routes: Vec<(RouteKey, Route)>) -> Result<(), RouteError> {
+        for (i, (route_key, _)) in routes.iter().enumerate() {
+            self.check_insert(route_key)?;
+            for (earlier, _) in routes[..i].iter() {
+                if earlier == route_key {
+                    return Err(RouteError::DuplicateRoute);
                 }
-                _ => {
-                    route_key.parts.push(RoutePart::Path(token.to_string()));
+                if self.reject_conflicts && keys_overlap(earlier, route_key) {
+                    return Err(RouteError::AmbiguousRoute);
                 }
             }
         }
-        let size = self.routes.len();
-        self.routes.insert(route_key, route);
-        if size != self.routes.len() - 1 {
-            return Err("a route identical to this one has already been defined!");
+        for (route_key, route) in routes {
+            self.insert(route_key, route)?;
         }
-        if has_string_vars {
-            self.svar_routes.push((route_key.clone(), route.clone()));
+        Ok(())
+    }
+
+    fn check_insert(&self, route_key: &RouteKey) -> Result<(), RouteError> {
+        if self.reject_conflicts
+            && self
+                .routes
+                .keys()
+                .any(|key| key != route_key && keys_overlap(key, route_key))
+        {
+            return Err(RouteError::AmbiguousRoute);
+        }
+        if self.fallback_guard
+            && route_key.domain.is_none()
+            && route_key.domain_set.is_empty()
+            && route_key.parts == [RoutePart::Wildcard]
+        {
+            return Err(RouteError::FallbackShadowed);
+        }
+        if self.routes.contains_key(route_key) {
+            return Err(RouteError::DuplicateRoute);
         }
         Ok(())
     }
+
+    fn store(&mut self, route_key: RouteKey, mut route: Route, seq: usize) {
+        route.seq = seq;
+        route.case_insensitive = self.case_insensitive;
+        if route_key.parts.contains(&RoutePart::String) {
+            self.svar_routes.push((route_key.clone(), route.clone()));
+        }
+        self.routes.insert(route_key, route);
+    }
+}
+
+// an ordered list of independent routers consulted in turn, the first one with a matching
+// route winning. Unlike registering everything on one router, each stays separate so whole
+// groups of routes (e.g. plugins) can be added or removed at once.
+#[derive(Clone, Default)]
+pub struct RouterChain {
+    routers: Vec<Router>,
+}
+
+impl RouterChain {
+    pub fn new() -> Self {
+        RouterChain {
+            routers: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, router: Router) {
+        self.routers.push(router);
+    }
+
+    pub fn insert(&mut self, index: usize, router: Router) {
+        self.routers.insert(index, router);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Router {
+        self.routers.remove(index)
+    }
+
+    pub fn routers(&self) -> &Vec<Router> {
+        &self.routers
+    }
+
+    pub fn find(&self, verb: Verb, path: &str, domain: Option<&str>) -> Option<Endpoint> {
+        self.find_route(verb, path, domain)
+            .map(|m| m.target_for(path))
+    }
+
+    pub fn find_route(
+        &self,
+        verb: Verb,
+        path: &str,
+        domain: Option<&str>,
+    ) -> Option<RouteMatch<'_>> {
+        self.routers
+            .iter()
+            .find_map(|router| router.find_route(verb.clone(), path, domain))
+    }
+
+    // dispatches to the first router with a matching route; when none match, the first
+    // router with a fallback renders it
+    pub fn dispatch(&self, verb: Verb, path: &str, domain: Option<&str>) -> Render {
+        let router = self
+            .routers
+            .iter()
+            .find(|router| router.find_route(verb.clone(), path, domain).is_some())
+            .or_else(|| self.routers.iter().find(|router| router.fallback.is_some()));
+        match router {
+            Some(router) => router.dispatch(verb, path, domain),
+            None => Render::not_found(),
+        }
+    }
 }
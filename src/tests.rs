@@ -1 +1,6 @@
+mod alloc_counter;
+mod render_tests;
+mod router_benches;
 mod router_tests;
+#[cfg(feature = "server")]
+mod server_tests;
@@ -0,0 +1,199 @@
+use crate::render::*;
+
+fn stream() -> Render {
+    let chunks = vec![
+        b"id,name\n".to_vec(),
+        b"1,sam\n".to_vec(),
+        b"2,ana\n".to_vec(),
+    ];
+    Render::Stream("text/csv".to_string(), Box::new(chunks.into_iter()))
+}
+
+#[test]
+fn test_stream_body() {
+    let render = stream();
+    assert_eq!(render.body_len(), None);
+    assert_eq!(render.content_type(), "text/csv");
+    let mut body = Vec::new();
+    render.write_body(&mut body).unwrap();
+    assert_eq!(body, b"id,name\n1,sam\n2,ana\n".to_vec());
+}
+
+#[test]
+fn test_stream_write_http_chunked() {
+    let mut out = Vec::new();
+    stream().write_http(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(out.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(!out.contains("Content-Length"));
+    assert!(out.ends_with("\r\n\r\n8\r\nid,name\n\r\n6\r\n1,sam\n\r\n6\r\n2,ana\n\r\n0\r\n\r\n"));
+}
+
+#[test]
+fn test_plain_write_http() {
+    let render = Render::Plain("hello".to_string());
+    assert_eq!(render.body_len(), Some(5));
+    let mut out = Vec::new();
+    render.write_http(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 5\r\n\r\nhello"
+    );
+}
+
+#[test]
+fn test_plain_content_type() {
+    let render = Render::Plain("hello".to_string());
+    assert_eq!(render.content_type(), "text/plain; charset=utf-8");
+    let render = Render::plain_with_type("<p>hello</p>", "text/html; charset=utf-8");
+    assert_eq!(render.content_type(), "text/html; charset=utf-8");
+    assert_eq!(render.body_len(), Some(12));
+}
+
+#[test]
+fn test_json_error_write_http() {
+    let render = Render::json_error(422, "bad input");
+    assert_eq!(render.status_code(), 422);
+    let mut out = Vec::new();
+    render.write_http(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 422 Unprocessable Entity\r\nContent-Type: application/json\r\n\
+         Content-Length: 21\r\n\r\n{\"error\":\"bad input\"}"
+    );
+}
+
+#[test]
+fn test_write_http_head() {
+    let mut out = Vec::new();
+    Render::Json("{\"ok\":true}".to_string())
+        .write_http_head(&mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 11\r\n\r\n"
+    );
+    let mut out = Vec::new();
+    stream().write_http_head(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 200 OK\r\nContent-Type: text/csv\r\n\r\n"
+    );
+}
+
+#[test]
+fn test_redirect() {
+    match Render::redirect("/login") {
+        Render::Redirect {
+            location,
+            permanent,
+        } => {
+            assert_eq!(location, "/login");
+            assert!(!permanent);
+        }
+        _ => panic!("expected a redirect"),
+    }
+    assert_eq!(Render::redirect("/login").status_code(), 302);
+    assert_eq!(Render::permanent_redirect("/home").status_code(), 301);
+    let mut out = Vec::new();
+    Render::redirect("/login").write_http(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 302 Found\r\nContent-Type: text/plain; charset=utf-8\r\nLocation: /login\r\nContent-Length: 0\r\n\r\n"
+    );
+}
+
+#[test]
+fn test_header_injection() {
+    let mut out = Vec::new();
+    Render::redirect("/x\r\nSet-Cookie: evil=1")
+        .with_header("X-Name\r\n", "a\nb")
+        .write_http(&mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 302 Found\r\nContent-Type: text/plain; charset=utf-8\r\nX-Name: ab\r\nLocation: /xSet-Cookie: evil=1\r\nContent-Length: 0\r\n\r\n"
+    );
+}
+
+#[test]
+fn test_status_constructors() {
+    let cases = vec![
+        (Render::not_found(), 404, "Not Found"),
+        (Render::created("made it"), 201, "made it"),
+        (Render::server_error(), 500, "Internal Server Error"),
+    ];
+    for (render, expected_code, expected_body) in cases {
+        assert_eq!(render.status_code(), expected_code);
+        assert_eq!(render.content_type(), "text/plain; charset=utf-8");
+        match render {
+            Render::Status { code, body, .. } => {
+                assert_eq!(code, expected_code);
+                assert_eq!(body, expected_body);
+            }
+            _ => panic!("expected a status render"),
+        }
+    }
+    assert_eq!(Render::status(418, "", "text/plain").status_code(), 418);
+}
+
+#[test]
+#[should_panic(expected = "invalid HTTP status code 600")]
+fn test_status_out_of_range() {
+    Render::status(600, "", "text/plain");
+}
+
+#[test]
+fn test_bytes() {
+    let data = [0x89, b'P', b'N', b'G', 0xff, 0x00];
+    let render = Render::bytes(&data, "image/png");
+    assert_eq!(render.content_type(), "image/png");
+    assert_eq!(render.body_len(), Some(6));
+    let mut out = Vec::new();
+    render.write_http(&mut out).unwrap();
+    let mut expected =
+        b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 6\r\n\r\n".to_vec();
+    expected.extend_from_slice(&data);
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn test_to_http_response() {
+    let plain = Render::Plain("hello".to_string()).to_http_response();
+    assert_eq!(
+        String::from_utf8(plain).unwrap(),
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 5\r\n\r\nhello"
+    );
+    let json = Render::Json("{\"a\":1}".to_string()).to_http_response();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"));
+    assert!(json.contains("Content-Length: 7\r\n\r\n{\"a\":1}"));
+    let missing = Render::File("/no/such/file".to_string(), "text/html".to_string());
+    assert!(String::from_utf8(missing.to_http_response())
+        .unwrap()
+        .starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    let render = Render::Plain("hi".to_string()).with_header("X-Test", "1");
+    let mut written = Vec::new();
+    let expected = render.to_http_response();
+    render.write_http(&mut written).unwrap();
+    assert_eq!(expected, written);
+}
+
+#[test]
+fn test_file_mime_inference() {
+    let cases = vec![
+        ("site/index.html", "text/html"),
+        ("style.css", "text/css"),
+        ("/static/app.js", "application/javascript"),
+        ("logo.PNG", "image/png"),
+        ("data.json", "application/json"),
+        ("archive.xyz", "application/octet-stream"),
+        ("README", "application/octet-stream"),
+    ];
+    for (path, expected) in cases {
+        assert_eq!(Render::file(path).content_type(), expected, "{}", path);
+    }
+    let overridden = Render::File("page.html".to_string(), "text/plain".to_string());
+    assert_eq!(overridden.content_type(), "text/plain");
+}
@@ -0,0 +1,70 @@
+// timing comparisons for hot paths; run with `cargo test --release -- --ignored --nocapture`
+
+use crate::router::*;
+use std::time::{Duration, Instant};
+
+fn target(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("this is a test".to_string())
+}
+
+fn bulk_paths(count: usize) -> Vec<&'static str> {
+    (0..count)
+        .map(|i| {
+            &*Box::leak(format!("/section{}/items/:id/#slug/page{}", i % 97, i).into_boxed_str())
+        })
+        .collect()
+}
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+#[test]
+#[ignore]
+fn bench_bulk_registration() {
+    let paths = bulk_paths(20_000);
+    let validated = time(|| {
+        let mut router = Router::new();
+        for path in paths.iter() {
+            router.route(None, Verb::Get, path, target).unwrap();
+        }
+    });
+    let prevalidated = time(|| {
+        let mut router = Router::new();
+        for path in paths.iter() {
+            router
+                .route_prevalidated(None, Verb::Get, path, target)
+                .unwrap();
+        }
+    });
+    println!(
+        "bulk registration of {} routes: validated {:?}, prevalidated {:?}",
+        paths.len(),
+        validated,
+        prevalidated
+    );
+}
+
+#[test]
+#[ignore]
+fn bench_lookup_scaling() {
+    for count in [100, 1_000, 10_000].iter() {
+        let mut router = Router::new();
+        for path in bulk_paths(*count) {
+            router.route(None, Verb::Get, path, target).unwrap();
+        }
+        let path = "/section3/items/5/thing/page3";
+        let iterations = 10_000;
+        let elapsed = time(|| {
+            for _ in 0..iterations {
+                router.find_route(Verb::Get, path, None).unwrap();
+            }
+        });
+        println!(
+            "{} lookups among {} routes: {:?}",
+            iterations, count, elapsed
+        );
+    }
+}
@@ -1,4 +1,6 @@
+use super::alloc_counter::allocations_during;
 use crate::router::*;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 
 fn target(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
     Render::Plain("this is a test".to_string())
@@ -47,7 +49,7 @@ fn test_route_parses_parts() {
         .unwrap();
     let routes = router.routes();
     assert_eq!(routes.len(), 1);
-    let route = router.routes().nth(0).unwrap();
+    let route = router.routes().next().unwrap();
     assert_eq!(route.verb, Verb::Patch);
     assert_eq!(route.vars.len(), 4);
     assert_eq!(route.vars[0], RouteVar::String("string"));
@@ -63,7 +65,7 @@ pub fn test_domain() {
     router
         .route(Some(domain), Verb::Post, "/some/path", target)
         .unwrap();
-    let route = router.routes().nth(0).unwrap();
+    let route = router.routes().next().unwrap();
     assert_eq!(route.verb, Verb::Post);
     assert_eq!(route.vars.len(), 0);
     assert_eq!(route.domain, Some(domain.to_string()));
@@ -76,7 +78,7 @@ pub fn test_domain_wildcard() {
     router
         .route(Some(domain), Verb::Post, "/some/path", target)
         .unwrap();
-    let route = router.routes().nth(0).unwrap();
+    let route = router.routes().next().unwrap();
     assert_eq!(route.verb, Verb::Post);
     assert_eq!(route.vars.len(), 0);
     assert_eq!(route.domain, Some(domain.to_string()));
@@ -131,7 +133,7 @@ pub fn test_url_parameters() {
 
 #[test]
 pub fn test_route_key_from_path() {
-    let key = RouteKey::from_path(Verb::Get, &"/contact/37".to_string()).unwrap();
+    let key = RouteKey::from_path(Verb::Get, "/contact/37").unwrap();
     assert_eq!(key.verb, Verb::Get);
     assert_eq!(key.domain, None);
     assert_eq!(key.parts[0], RoutePart::Path("contact".to_string()));
@@ -140,12 +142,7 @@ pub fn test_route_key_from_path() {
 
 #[test]
 pub fn test_route_key_from_path_with_domain() {
-    let key = RouteKey::new(
-        Verb::Get,
-        &"/contact/33".to_string(),
-        Some(&"domain.com".to_string()),
-    )
-    .unwrap();
+    let key = RouteKey::new(Verb::Get, "/contact/33", Some("domain.com")).unwrap();
     assert_eq!(key.verb, Verb::Get);
     assert_eq!(key.domain, Some("domain.com".to_string()));
     assert_eq!(key.parts[0], RoutePart::Path("contact".to_string()));
@@ -177,7 +174,2913 @@ pub fn test_router_find() {
         .verb(Verb::Get)
         .route(target)
         .unwrap();
-    router.find(Verb::Post, &("/hello/world".to_string()), Some(&("domain.com".to_string()))).unwrap();
-    router.find(Verb::Patch, &("/hello/puppet".to_string()), Some(&("domain.com".to_string()))).unwrap();
-    router.find(Verb::Delete, &("/goodbye/33".to_string()), None).unwrap();
+    router
+        .find(Verb::Post, "/hello/world", Some("domain.com"))
+        .unwrap();
+    router
+        .find(Verb::Patch, "/hello/puppet", Some("domain.com"))
+        .unwrap();
+    router.find(Verb::Delete, "/goodbye/33", None).unwrap();
+}
+
+#[test]
+pub fn test_fallback_guard() {
+    let mut router = Router::new();
+    router.set_fallback(target);
+    router.set_fallback_guard(true);
+    assert_eq!(
+        router.route(None, Verb::Get, "/*path", target),
+        Err(RouteError::FallbackShadowed)
+    );
+    assert_eq!(
+        router.route(None, Verb::Get, "/static/*path", target),
+        Ok(())
+    );
+    assert_eq!(
+        router.route(Some("domain.com"), Verb::Get, "/*path", target),
+        Ok(())
+    );
+    router.set_fallback_guard(false);
+    assert_eq!(router.route(None, Verb::Get, "/*path", target), Ok(()));
+}
+
+#[test]
+pub fn test_route_match_template() {
+    let mut router = Router::new();
+    router.path("/users/:id").get().route(target).unwrap();
+    router
+        .path("/users/:id/posts/#slug")
+        .get()
+        .route(target)
+        .unwrap();
+    let m = router.find_route(Verb::Get, "/users/12345", None).unwrap();
+    assert_eq!(m.template(), "/users/:id");
+    assert_eq!(m.params()["id"], UrlParam::Int(12345));
+    let m = router
+        .find_route(Verb::Get, "/users/7/posts/hello-world", None)
+        .unwrap();
+    assert_eq!(m.template(), "/users/:id/posts/#slug");
+}
+
+#[test]
+pub fn test_route_format_extension() {
+    let mut router = Router::new();
+    router.path("/users/:id").format().route(target).unwrap();
+    let m = router.find_route(Verb::Get, "/users/5.json", None).unwrap();
+    assert_eq!(m.params()["id"], UrlParam::Int(5));
+    assert_eq!(m.params()["format"], UrlParam::String("json".to_string()));
+    let m = router.find_route(Verb::Get, "/users/5.xml", None).unwrap();
+    assert_eq!(m.params()["id"], UrlParam::Int(5));
+    assert_eq!(m.params()["format"], UrlParam::String("xml".to_string()));
+    let m = router.find_route(Verb::Get, "/users/5", None).unwrap();
+    assert_eq!(m.params()["id"], UrlParam::Int(5));
+    assert_eq!(m.params().get("format"), None);
+}
+
+#[test]
+pub fn test_export_pairs() {
+    let mut router = Router::new();
+    router.path("/users/:id").delete().route(target).unwrap();
+    router.path("/users").post().route(target).unwrap();
+    router.path("/users/:id").get().route(target).unwrap();
+    router.path("/users").get().route(target).unwrap();
+    assert_eq!(
+        router.export_pairs(),
+        vec![
+            (Verb::Get, "/users".to_string()),
+            (Verb::Post, "/users".to_string()),
+            (Verb::Get, "/users/:id".to_string()),
+            (Verb::Delete, "/users/:id".to_string()),
+        ]
+    );
+}
+
+fn echo_limit(_url: &UrlParams, get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain(format!("{:?}", get.get("limit")))
+}
+
+fn plain_body(render: Render) -> String {
+    match render {
+        Render::Plain(body) => body,
+        _ => panic!("expected a plain render"),
+    }
+}
+
+#[test]
+pub fn test_query_param_defaults() {
+    let mut router = Router::new();
+    router
+        .route(None, Verb::Get, "/list?limit=:int=20", echo_limit)
+        .unwrap();
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/list", None)),
+        "Some(Int(20))"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/list?limit=5", None)),
+        "Some(Int(5))"
+    );
+    assert_eq!(
+        router.route(None, Verb::Get, "/other?limit=:int=many", echo_limit),
+        Err(RouteError::InvalidQuerySpec)
+    );
+}
+
+#[test]
+pub fn test_merged_params_precedence() {
+    let mut url = UrlParams::new();
+    url.add("id", UrlParam::Int(5));
+    let mut get = VerbParams::new();
+    get.add("id".to_string(), VerbParam::String("get".to_string()));
+    get.add("name".to_string(), VerbParam::String("get".to_string()));
+    get.add("page".to_string(), VerbParam::Int(2));
+    let mut post = VerbParams::new();
+    post.add("id".to_string(), VerbParam::String("post".to_string()));
+    post.add("name".to_string(), VerbParam::String("post".to_string()));
+    let merged = VerbParams::merged(&url, &get, &post);
+    assert_eq!(merged["id"], VerbParam::Int(5));
+    assert_eq!(merged["name"], VerbParam::String("post".to_string()));
+    assert_eq!(merged["page"], VerbParam::Int(2));
+}
+
+fn variant_a(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("a".to_string())
+}
+
+fn variant_b(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("b".to_string())
+}
+
+fn select_variant(_url: &UrlParams, get: &VerbParams, _post: &VerbParams) -> Endpoint {
+    match get.get("beta") {
+        Some(_) => variant_b,
+        None => variant_a,
+    }
+}
+
+#[test]
+pub fn test_route_dynamic() {
+    let mut router = Router::new();
+    router
+        .route_dynamic(Verb::Get, "/landing", select_variant)
+        .unwrap();
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/landing", None)),
+        "a"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/landing?beta=1", None)),
+        "b"
+    );
+}
+
+#[test]
+pub fn test_verify_against_registry() {
+    let mut registry = HandlerRegistry::new();
+    registry.register("users#index", target);
+    registry.register("users#show", target);
+    let mut router = Router::new();
+    router
+        .path("/users")
+        .route_to(&registry, "users#index")
+        .unwrap();
+    router
+        .path("/users/:id")
+        .route_to(&registry, "users#show")
+        .unwrap();
+    assert_eq!(
+        router.path("/posts").route_to(&registry, "posts#index"),
+        Err(RouteError::UnknownHandler)
+    );
+    assert!(router.verify_against(&registry).is_ok());
+
+    let mut deployed = HandlerRegistry::new();
+    deployed.register("users#index", target);
+    deployed.register("posts#index", target);
+    let report = router.verify_against(&deployed);
+    assert_eq!(report.missing, vec!["users#show"]);
+    assert_eq!(report.unused, vec!["posts#index"]);
+}
+
+fn apex(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("apex".to_string())
+}
+
+fn subdomain(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("subdomain".to_string())
+}
+
+#[test]
+pub fn test_apex_and_wildcard_domains_are_distinct() {
+    let mut router = Router::new();
+    router
+        .route(Some("example.com"), Verb::Get, "/", apex)
+        .unwrap();
+    router
+        .route(Some("*.example.com"), Verb::Get, "/", subdomain)
+        .unwrap();
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/", Some("example.com"))),
+        "apex"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/", Some("api.example.com"))),
+        "subdomain"
+    );
+
+    let mut router = Router::new();
+    router
+        .route(Some("*.example.com"), Verb::Get, "/", subdomain)
+        .unwrap();
+    assert!(router.find(Verb::Get, "/", Some("example.com")).is_none());
+
+    let mut router = Router::new();
+    router
+        .route(Some("example.com"), Verb::Get, "/", apex)
+        .unwrap();
+    assert!(router
+        .find(Verb::Get, "/", Some("api.example.com"))
+        .is_none());
+}
+
+#[test]
+pub fn test_route_is_static() {
+    let mut router = Router::new();
+    router.path("/about/team").get().route(target).unwrap();
+    router.path("/users/:id").get().route(target).unwrap();
+    router.path("/feed").format().route(target).unwrap();
+    let route = |path| router.find_route(Verb::Get, path, None).unwrap().route();
+    assert!(route("/about/team").is_static());
+    assert!(!route("/users/5").is_static());
+    assert!(!route("/feed").is_static());
+    assert!(RoutePart::Int.is_dynamic());
+    assert!(!RoutePart::Path("about".to_string()).is_dynamic());
+}
+
+#[test]
+pub fn test_route_deprecation() {
+    let mut router = Router::new();
+    router.path("/v1/users").deprecated().route(target).unwrap();
+    router
+        .path("/v1/posts")
+        .sunset("Sat, 01 Nov 2025 00:00:00 GMT")
+        .route(target)
+        .unwrap();
+    router.path("/v2/users").route(target).unwrap();
+    let route = router
+        .find_route(Verb::Get, "/v1/users", None)
+        .unwrap()
+        .route();
+    assert!(route.is_deprecated());
+    assert_eq!(route.sunset(), None);
+    let route = router
+        .find_route(Verb::Get, "/v1/posts", None)
+        .unwrap()
+        .route();
+    assert!(route.is_deprecated());
+    assert_eq!(route.sunset(), Some("Sat, 01 Nov 2025 00:00:00 GMT"));
+    let route = router
+        .find_route(Verb::Get, "/v2/users", None)
+        .unwrap()
+        .route();
+    assert!(!route.is_deprecated());
+}
+
+#[test]
+pub fn test_route_param_diff() {
+    let mut router = Router::new();
+    router.path("/users/:id/#tab").get().route(target).unwrap();
+    let route = router.routes().next().unwrap();
+    assert_eq!(
+        route.param_diff("/users/1/posts", "/users/2/posts"),
+        Some(vec!["id"])
+    );
+    assert_eq!(
+        route.param_diff("/users/1/posts", "/users/2/likes"),
+        Some(vec!["id", "tab"])
+    );
+    assert_eq!(
+        route.param_diff("/users/1/posts", "/users/1/posts"),
+        Some(vec![])
+    );
+    assert_eq!(route.param_diff("/users/1/posts", "/posts/1"), None);
+}
+
+#[test]
+pub fn test_route_format_whitelist() {
+    let mut router = Router::new();
+    router
+        .path("/users")
+        .formats(&["json", "xml"])
+        .route(target)
+        .unwrap();
+    let m = router.find_route(Verb::Get, "/users.json", None).unwrap();
+    assert_eq!(m.params()["format"], UrlParam::String("json".to_string()));
+    let m = router.find_route(Verb::Get, "/users.xml", None).unwrap();
+    assert_eq!(m.params()["format"], UrlParam::String("xml".to_string()));
+    let m = router.find_route(Verb::Get, "/users", None).unwrap();
+    assert_eq!(m.params().get("format"), None);
+    assert!(router.find_route(Verb::Get, "/users.exe", None).is_none());
+}
+
+fn me(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("me".to_string())
+}
+
+#[test]
+pub fn test_route_alias() {
+    let mut router = Router::new();
+    router.path("/users/me").get().route(me).unwrap();
+    router.path("/users/:id/posts").get().route(target).unwrap();
+    assert_eq!(router.alias("/profile", "/users/me"), Ok(()));
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/profile", None)),
+        "me"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/users/me", None)),
+        "me"
+    );
+    assert_eq!(router.alias("/p/:id", "/users/:id/posts"), Ok(()));
+    let m = router.find_route(Verb::Get, "/p/3", None).unwrap();
+    assert_eq!(m.params()["id"], UrlParam::Int(3));
+    assert_eq!(
+        router.alias("/settings", "/users/settings"),
+        Err(RouteError::UnknownRoute)
+    );
+    assert_eq!(
+        router.alias("/posts/#name", "/users/:id/posts"),
+        Err(RouteError::IncompatibleAlias)
+    );
+}
+
+#[test]
+pub fn test_ensure_idempotent() {
+    let mut router = Router::new();
+    assert_eq!(router.ensure(None, Verb::Get, "/users/:id", target), Ok(()));
+    assert_eq!(router.ensure(None, Verb::Get, "/users/:id", target), Ok(()));
+    assert_eq!(router.routes().len(), 1);
+    assert_eq!(
+        router.ensure(None, Verb::Get, "/users/:id", me),
+        Err(RouteError::DuplicateRoute)
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/users/1", None)),
+        "this is a test"
+    );
+}
+
+#[test]
+pub fn test_url_params_raw() {
+    let mut router = Router::new();
+    router.path("/orders/:id/#ref").get().route(target).unwrap();
+    let m = router
+        .find_route(Verb::Get, "/orders/00042/abc", None)
+        .unwrap();
+    assert_eq!(m.params()["id"], UrlParam::Int(42));
+    assert_eq!(m.params().raw("id"), Some("00042"));
+    assert_eq!(m.params().raw("ref"), Some("abc"));
+    assert_eq!(m.params().raw("missing"), None);
+}
+
+#[test]
+pub fn test_split_wildcard() {
+    let mut router = Router::new();
+    router
+        .path("/proxy/*rest")
+        .split_wildcard()
+        .route(target)
+        .unwrap();
+    router.path("/static/*path").route(target).unwrap();
+    let m = router.find_route(Verb::Get, "/proxy/a/b/c", None).unwrap();
+    assert_eq!(
+        m.params()["rest"],
+        UrlParam::Array(vec![
+            UrlParam::String("a".to_string()),
+            UrlParam::String("b".to_string()),
+            UrlParam::String("c".to_string()),
+        ])
+    );
+    assert_eq!(m.params().raw("rest"), Some("a/b/c"));
+    let m = router.find_route(Verb::Get, "/static/a/b/c", None).unwrap();
+    assert_eq!(m.params()["path"], UrlParam::String("a/b/c".to_string()));
+}
+
+#[test]
+pub fn test_coverage() {
+    let mut router = Router::new();
+    router.path("/users").get().route(target).unwrap();
+    router.path("/users").post().route(target).unwrap();
+    router.path("/users/:id").get().route(target).unwrap();
+    router.path("/admin").get().route(target).unwrap();
+    let report = router.coverage(&["/users", "/users/5", "/nowhere"]);
+    assert_eq!(
+        report.hit,
+        vec![
+            (Verb::Get, "/users".to_string()),
+            (Verb::Post, "/users".to_string()),
+            (Verb::Get, "/users/:id".to_string()),
+        ]
+    );
+    assert_eq!(report.unhit, vec![(Verb::Get, "/admin".to_string())]);
+    assert_eq!(report.unmatched, vec!["/nowhere".to_string()]);
+}
+
+#[test]
+pub fn test_coverage_of_domain_routes() {
+    let mut router = Router::new();
+    router
+        .route(Some("example.com"), Verb::Get, "/status", apex)
+        .unwrap();
+    router
+        .route(Some("other.com"), Verb::Get, "/status", subdomain)
+        .unwrap();
+    let report = router.coverage(&["https://example.com:8080/status", "/status"]);
+    assert_eq!(report.hit, vec![(Verb::Get, "/status".to_string())]);
+    assert_eq!(report.unhit, vec![(Verb::Get, "/status".to_string())]);
+    assert_eq!(report.unmatched, vec!["/status".to_string()]);
+}
+
+#[test]
+pub fn test_host_optional() {
+    let mut router = Router::new();
+    router
+        .route(Some("example.com"), Verb::Get, "/status", apex)
+        .unwrap();
+    router
+        .route(Some("other.com"), Verb::Get, "/status", subdomain)
+        .unwrap();
+    assert!(router.find(Verb::Get, "/status", None).is_none());
+    router.set_host_optional(true);
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/status", None)),
+        "apex"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/status", Some("other.com"))),
+        "subdomain"
+    );
+}
+
+#[test]
+pub fn test_host_optional_keeps_registration_order() {
+    let mut router = Router::new();
+    router.route(None, Verb::Get, "/status", me).unwrap();
+    router
+        .route(Some("*.example.com"), Verb::Get, "/status", subdomain)
+        .unwrap();
+    router
+        .route(Some("example.com"), Verb::Get, "/status", apex)
+        .unwrap();
+    router.set_host_optional(true);
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/status", None)),
+        "me"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/status", Some("example.com"))),
+        "apex"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/status", Some("a.example.com"))),
+        "subdomain"
+    );
+}
+
+#[test]
+pub fn test_route_paths() {
+    let mut router = Router::new();
+    assert_eq!(
+        router.route_paths(Verb::Get, &["/", "/home", "/index"], me),
+        Ok(())
+    );
+    for path in ["/", "/home", "/index"] {
+        assert_eq!(plain_body(router.dispatch(Verb::Get, path, None)), "me");
+    }
+    assert_eq!(
+        router.route_paths(Verb::Get, &["/home", "/welcome", "bad"], me),
+        Err(vec![
+            ("/home", RouteError::DuplicateRoute),
+            ("bad", RouteError::InvalidPathFormat),
+        ])
+    );
+    assert!(router.find(Verb::Get, "/welcome", None).is_some());
+}
+
+#[test]
+pub fn test_router_snapshot() {
+    let mut a = Router::new();
+    a.path("/users/:id").format().route(target).unwrap();
+    a.path("/users").post().route(target).unwrap();
+    a.path("/old")
+        .domain("example.com")
+        .deprecated()
+        .route(target)
+        .unwrap();
+    a.route(None, Verb::Get, "/list?limit=:int=20", target)
+        .unwrap();
+    let mut b = Router::new();
+    b.route(None, Verb::Get, "/list?limit=:int=20", me).unwrap();
+    b.path("/old")
+        .domain("example.com")
+        .deprecated()
+        .route(me)
+        .unwrap();
+    b.path("/users").post().route(me).unwrap();
+    b.path("/users/:id").format().route(me).unwrap();
+    let snapshot = "
+GET - /list?limit=:int=20
+GET - /users/:id format()
+GET example.com /old meta(deprecated=true)
+POST - /users
+";
+    assert_eq!(a.to_snapshot(), b.to_snapshot());
+    assert!(a.snapshot_eq(snapshot));
+    assert!(b.snapshot_eq(snapshot));
+    b.path("/extra").route(me).unwrap();
+    assert!(!b.snapshot_eq(snapshot));
+}
+
+#[test]
+pub fn test_route_error_status_code() {
+    assert_eq!(RouteError::InvalidPathFormat.status_code(), 400);
+    assert_eq!(RouteError::InvalidDomain.status_code(), 400);
+    assert_eq!(RouteError::ParamParseFailure.status_code(), 400);
+    assert_eq!(RouteError::UnknownRoute.status_code(), 404);
+    assert_eq!(RouteError::DuplicateRoute.status_code(), 500);
+    assert_eq!(RouteError::FallbackShadowed.status_code(), 500);
+    assert_eq!(RouteError::InvalidQuerySpec.status_code(), 500);
+    assert_eq!(RouteError::UnknownHandler.status_code(), 500);
+    assert_eq!(RouteError::IncompatibleAlias.status_code(), 500);
+}
+
+#[test]
+pub fn test_route_prevalidated() {
+    let mut router = Router::new();
+    assert_eq!(
+        router.route_prevalidated(None, Verb::Get, "/users/:id", target),
+        Ok(())
+    );
+    let m = router.find_route(Verb::Get, "/users/5", None).unwrap();
+    assert_eq!(m.params()["id"], UrlParam::Int(5));
+    assert_eq!(
+        router.route_prevalidated(None, Verb::Get, "/users/:id", target),
+        Err(RouteError::DuplicateRoute)
+    );
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+pub fn test_route_prevalidated_checks_in_debug() {
+    let mut router = Router::new();
+    let _ = router.route_prevalidated(None, Verb::Get, "no/leading/slash", target);
+}
+
+#[test]
+pub fn test_matrix_params() {
+    let mut router = Router::new();
+    router.path("/users/:id").get().route(target).unwrap();
+    router.path("/prices/;amount").get().route(target).unwrap();
+    assert!(router
+        .find_route(Verb::Get, "/users;role=admin/5", None)
+        .is_none());
+    router.set_matrix_params(true);
+    let m = router
+        .find_route(Verb::Get, "/users;role=admin;active/5", None)
+        .unwrap();
+    assert_eq!(m.params()["id"], UrlParam::Int(5));
+    assert_eq!(m.params()["role"], UrlParam::String("admin".to_string()));
+    assert_eq!(m.params()["active"], UrlParam::String("".to_string()));
+    let m = router
+        .find_route(Verb::Get, "/prices/9.5;currency=eur", None)
+        .unwrap();
+    assert_eq!(m.params()["amount"], UrlParam::Float(9.5));
+    assert_eq!(m.params()["currency"], UrlParam::String("eur".to_string()));
+}
+
+#[test]
+pub fn test_wildcard_only_routes() {
+    let mut router = Router::new();
+    router
+        .path("/dashboard")
+        .domain("*.example.com")
+        .route(target)
+        .unwrap();
+    router
+        .path("/dashboard")
+        .domain("app.example.com")
+        .route(target)
+        .unwrap();
+    router
+        .path("/admin")
+        .domain("*.example.com")
+        .route(target)
+        .unwrap();
+    router.path("/admin").route(target).unwrap();
+    router
+        .path("/status")
+        .domain("status.example.com")
+        .route(target)
+        .unwrap();
+    router.path("/health").route(target).unwrap();
+    router
+        .path("/debug")
+        .domain("*.internal.net")
+        .post()
+        .route(target)
+        .unwrap();
+    let routes: Vec<(Option<String>, String)> = router
+        .wildcard_only_routes()
+        .into_iter()
+        .map(|route| (route.domain.clone(), route.to_path_string()))
+        .collect();
+    assert_eq!(
+        routes,
+        vec![
+            (Some("*.example.com".to_string()), "/admin".to_string()),
+            (Some("*.internal.net".to_string()), "/debug".to_string()),
+        ]
+    );
+}
+
+#[test]
+pub fn test_route_match_metadata_reads_do_not_allocate() {
+    let mut router = Router::new();
+    router
+        .path("/users/:id/#tab")
+        .sunset("2030-01-01")
+        .route(target)
+        .unwrap();
+    let m = router
+        .find_route(Verb::Get, "/users/5/posts", None)
+        .unwrap();
+    let (allocations, (deprecated, sunset, vars, key_parts)) = allocations_during(|| {
+        let route = m.route();
+        (
+            route.is_deprecated(),
+            route.sunset(),
+            route.vars.len(),
+            m.key().parts().len(),
+        )
+    });
+    assert_eq!(allocations, 0);
+    assert!(deprecated);
+    assert_eq!(sunset, Some("2030-01-01"));
+    assert_eq!(vars, 2);
+    assert_eq!(key_parts, 3);
+    assert!(std::ptr::eq(m.route(), router.routes().next().unwrap()));
+}
+
+struct InvalidEmail;
+
+impl HandlerError for InvalidEmail {
+    fn status(&self) -> u16 {
+        422
+    }
+
+    fn message(&self) -> String {
+        "email is \"invalid\"".to_string()
+    }
+}
+
+fn signup(
+    _url: &UrlParams,
+    get: &VerbParams,
+    _post: &VerbParams,
+) -> Result<Render, Box<dyn HandlerError>> {
+    match get.get("email") {
+        Some(VerbParam::String(email)) if email.contains('@') => Ok(Render::Json("{}".to_string())),
+        _ => Err(Box::new(InvalidEmail)),
+    }
+}
+
+fn status_parts(render: Render) -> (u16, String, String) {
+    match render {
+        Render::Status {
+            code,
+            body,
+            content_type,
+        } => (code, body, content_type),
+        _ => panic!("expected a status render"),
+    }
+}
+
+#[test]
+pub fn test_json_errors() {
+    let mut router = Router::new();
+    router
+        .route_fallible(None, Verb::Get, "/signup", signup)
+        .unwrap();
+    assert!(matches!(
+        router.dispatch(Verb::Get, "/signup?email=a@b.com", None),
+        Render::Json(_)
+    ));
+    assert_eq!(
+        status_parts(router.dispatch(Verb::Get, "/signup?email=nope", None)),
+        (
+            422,
+            "email is \"invalid\"".to_string(),
+            "text/plain; charset=utf-8".to_string()
+        )
+    );
+    router.set_json_errors(true);
+    assert_eq!(
+        status_parts(router.dispatch(Verb::Get, "/signup?email=nope", None)),
+        (
+            422,
+            "{\"error\":\"email is \\\"invalid\\\"\"}".to_string(),
+            "application/json".to_string()
+        )
+    );
+}
+
+#[test]
+pub fn test_unused_params() {
+    let mut registry = HandlerRegistry::new();
+    registry.register("posts#show", target);
+    let mut router = Router::new();
+    router.path("/users/:id/#tab").route(target).unwrap();
+    router
+        .path("/posts/:id/#slug")
+        .route_to(&registry, "posts#show")
+        .unwrap();
+    router.path("/teams/:id").route(target).unwrap();
+    let mut usage = std::collections::HashMap::new();
+    usage.insert("/users/:id/#tab", vec!["tab"]);
+    usage.insert("posts#show", vec!["id", "slug"]);
+    let unused = router.unused_params(&usage);
+    assert_eq!(unused.len(), 1);
+    assert_eq!(unused[0].0.parts()[0], RoutePart::Path("users".to_string()));
+    assert_eq!(unused[0].1, "id");
+}
+
+#[test]
+pub fn test_strip_prefix() {
+    let mut router = Router::new();
+    router.path("/users/:id").route(target).unwrap();
+    router.path("/").route(me).unwrap();
+    router.set_strip_prefix("/myapp/");
+    let m = router
+        .find_route(Verb::Get, "/myapp/users/5", None)
+        .unwrap();
+    assert_eq!(m.params()["id"], UrlParam::Int(5));
+    assert_eq!(plain_body(router.dispatch(Verb::Get, "/myapp", None)), "me");
+    assert!(router.find(Verb::Get, "/users/5", None).is_none());
+    assert!(router.find(Verb::Get, "/myappx/users/5", None).is_none());
+    router.set_prefix_required(false);
+    assert!(router.find(Verb::Get, "/users/5", None).is_some());
+    assert!(router.find(Verb::Get, "/myapp/users/5", None).is_some());
+}
+
+#[test]
+pub fn test_static_get_urls() {
+    let mut router = Router::new();
+    router.path("/").route(target).unwrap();
+    router.path("/about").route(target).unwrap();
+    router.path("/users/:id").route(target).unwrap();
+    router.path("/contact").post().route(target).unwrap();
+    router
+        .path("/docs")
+        .domain("docs.example.com")
+        .route(target)
+        .unwrap();
+    router
+        .path("/home")
+        .domain("*.example.com")
+        .route(target)
+        .unwrap();
+    assert_eq!(
+        router.static_get_urls("https://example.com/"),
+        vec![
+            "https://docs.example.com/docs".to_string(),
+            "https://example.com/".to_string(),
+            "https://example.com/about".to_string(),
+        ]
+    );
+}
+
+fn slow(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    Render::Plain("slow".to_string())
+}
+
+fn timed_out(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("timed out".to_string())
+}
+
+#[test]
+pub fn test_route_timeout() {
+    use std::time::Duration;
+    let mut router = Router::new();
+    router
+        .path("/slow")
+        .timeout(Duration::from_millis(20))
+        .route(slow)
+        .unwrap();
+    router
+        .path("/fast")
+        .timeout(Duration::from_secs(5))
+        .route(me)
+        .unwrap();
+    assert_eq!(
+        status_parts(router.dispatch(Verb::Get, "/slow", None)),
+        (
+            504,
+            "Gateway Timeout".to_string(),
+            "text/plain; charset=utf-8".to_string()
+        )
+    );
+    assert_eq!(plain_body(router.dispatch(Verb::Get, "/fast", None)), "me");
+    router.set_timeout_handler(timed_out);
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/slow", None)),
+        "timed out"
+    );
+}
+
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+static MOST_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+fn counted_slow(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    let running = RUNNING.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+    MOST_RUNNING.fetch_max(running, AtomicOrdering::SeqCst);
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    RUNNING.fetch_sub(1, AtomicOrdering::SeqCst);
+    Render::Plain("slow".to_string())
+}
+
+#[test]
+pub fn test_timed_out_handlers_are_bounded() {
+    let mut router = Router::new();
+    router
+        .path("/slow")
+        .timeout(std::time::Duration::from_millis(5))
+        .route(counted_slow)
+        .unwrap();
+    for _ in 0..TIMEOUT_WORKERS * 3 {
+        assert_eq!(router.dispatch(Verb::Get, "/slow", None).status_code(), 504);
+    }
+    assert!(MOST_RUNNING.load(AtomicOrdering::SeqCst) <= TIMEOUT_WORKERS);
+}
+
+#[test]
+pub fn test_longest_prefix() {
+    let mut router = Router::new();
+    router.path("/").route(target).unwrap();
+    router.path("/a").route(target).unwrap();
+    router.path("/a/b").route(target).unwrap();
+    router.path("/a/b/c/d").route(target).unwrap();
+    router.path("/users/:id").route(target).unwrap();
+    router.path("/files/*rest").route(target).unwrap();
+    router.path("/caf%C3%A9").route(target).unwrap();
+    let prefix = |path| router.longest_prefix(path, None).unwrap().to_path_string();
+    assert_eq!(prefix("/a/b/c"), "/a/b");
+    assert_eq!(prefix("/a/b/c/d/e"), "/a/b/c/d");
+    assert_eq!(prefix("/a/x"), "/a");
+    assert_eq!(prefix("/users/5/settings"), "/users/:id");
+    assert_eq!(prefix("/nothing/here"), "/");
+    assert_eq!(prefix("/files/x/y/z"), "/files/*rest");
+    assert_eq!(prefix("/caf%C3%A9/menu"), "/café");
+    assert!(Router::new().longest_prefix("/a", None).is_none());
+}
+
+#[test]
+pub fn test_date_segment() {
+    let mut router = Router::new();
+    router.path("/archive/@day").route(target).unwrap();
+    router
+        .path("/posts/@day")
+        .date_segments()
+        .route(target)
+        .unwrap();
+    let day = |path| {
+        router
+            .find_route(Verb::Get, path, None)
+            .map(|m| m.params()["day"].clone())
+    };
+    let expected = Some(UrlParam::Date(Date::new(2024, 1, 15).unwrap()));
+    assert_eq!(day("/archive/2024-01-15"), expected);
+    assert_eq!(day("/posts/2024/01/15"), expected);
+    assert_eq!(
+        day("/archive/2024-02-29"),
+        Some(UrlParam::Date(Date::new(2024, 2, 29).unwrap()))
+    );
+    assert_eq!(day("/archive/2024-13-40"), None);
+    assert_eq!(day("/archive/2023-02-29"), None);
+    assert_eq!(day("/archive/2024-1-15"), None);
+    assert_eq!(day("/posts/2024/13/40"), None);
+    assert_eq!(day("/posts/2024/01"), None);
+    assert_eq!(day("/posts/2024/01/15/extra"), None);
+}
+
+#[test]
+pub fn test_router_chain() {
+    let mut plugin = Router::new();
+    plugin.path("/users/me").route(me).unwrap();
+    let mut app = Router::new();
+    app.path("/users/#name").route(target).unwrap();
+    app.path("/about").route(target).unwrap();
+    let mut chain = RouterChain::new();
+    chain.push(plugin);
+    chain.push(app);
+    assert_eq!(
+        plain_body(chain.dispatch(Verb::Get, "/users/me", None)),
+        "me"
+    );
+    assert_eq!(
+        plain_body(chain.dispatch(Verb::Get, "/users/sam", None)),
+        "this is a test"
+    );
+    assert_eq!(
+        chain
+            .find_route(Verb::Get, "/about", None)
+            .unwrap()
+            .template(),
+        "/about"
+    );
+    assert!(chain.find(Verb::Get, "/missing", None).is_none());
+    assert_eq!(
+        chain.dispatch(Verb::Get, "/missing", None).status_code(),
+        404
+    );
+    chain.remove(0);
+    assert_eq!(
+        plain_body(chain.dispatch(Verb::Get, "/users/me", None)),
+        "this is a test"
+    );
+}
+
+#[test]
+pub fn test_repeat_segment() {
+    let mut router = Router::new();
+    router.path("/tags/+tags").route(target).unwrap();
+    let m = router.find_route(Verb::Get, "/tags/a/b", None).unwrap();
+    assert_eq!(
+        m.params()["tags"],
+        UrlParam::Array(vec![
+            UrlParam::String("a".to_string()),
+            UrlParam::String("b".to_string())
+        ])
+    );
+    assert_eq!(m.params().raw("tags"), Some("a/b"));
+    assert_eq!(m.template(), "/tags/+tags");
+    assert!(router.find_route(Verb::Get, "/tags/rust", None).is_some());
+    assert!(router.find_route(Verb::Get, "/tags", None).is_none());
+    assert!(router.find_route(Verb::Get, "/tags/", None).is_none());
+    assert_eq!(
+        router.route(None, Verb::Get, "/tags/+tags/more", target),
+        Err(RouteError::WildcardNotLast)
+    );
+}
+
+#[test]
+pub fn test_sigils_inside_literals() {
+    let mut router = Router::new();
+    let paths = [
+        "/c++",
+        "/a+b",
+        "/a,b",
+        "/user@host",
+        "/wow!",
+        "/price$",
+        "/x^y",
+        "/a*b",
+    ];
+    for path in paths.iter() {
+        router.route(None, Verb::Get, path, target).unwrap();
+    }
+    for path in paths.iter() {
+        assert_eq!(
+            router.find_route(Verb::Get, path, None).unwrap().template(),
+            *path
+        );
+    }
+    assert_eq!(
+        router.route(None, Verb::Get, "/a;b", target),
+        Err(RouteError::InvalidPathFormat)
+    );
+}
+
+#[test]
+pub fn test_log_key() {
+    let mut router = Router::new();
+    router.path("/users/:id").delete().route(target).unwrap();
+    let m = router.find_route(Verb::Delete, "/users/42", None).unwrap();
+    assert_eq!(m.log_key(), "DELETE /users/:id");
+}
+
+#[test]
+pub fn test_absent_query_constraint() {
+    let mut router = Router::new();
+    router.path("/feed?!format").route(me).unwrap();
+    router.path("/feed?format=:string").route(target).unwrap();
+    assert_eq!(plain_body(router.dispatch(Verb::Get, "/feed", None)), "me");
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/feed?format=rss", None)),
+        "this is a test"
+    );
+    assert_eq!(
+        router
+            .find_route(Verb::Get, "/feed?page=2", None)
+            .unwrap()
+            .template(),
+        "/feed"
+    );
+    assert_eq!(
+        router.route(None, Verb::Get, "/feed?!format", target),
+        Err(RouteError::DuplicateRoute)
+    );
+    assert_eq!(
+        router.route(None, Verb::Get, "/feed?!", target),
+        Err(RouteError::InvalidQuerySpec)
+    );
+    assert!(router.to_snapshot().contains("GET - /feed?!format"));
+}
+
+#[test]
+pub fn test_unverbed_builder() {
+    let mut router = Router::new();
+    router.on("/items").post().route(target).unwrap();
+    router
+        .on("/items/:id")
+        .verb(Verb::Put)
+        .route(target)
+        .unwrap();
+    assert!(router.find(Verb::Post, "/items", None).is_some());
+    assert!(router.find(Verb::Get, "/items", None).is_none());
+    assert!(router.find(Verb::Put, "/items/3", None).is_some());
+}
+
+#[derive(Debug, PartialEq)]
+struct RateLimit {
+    per_minute: u32,
+}
+
+#[test]
+pub fn test_route_extensions() {
+    let mut router = Router::new();
+    router
+        .path("/search")
+        .ext(RateLimit { per_minute: 30 })
+        .route(target)
+        .unwrap();
+    router.path("/other").route(target).unwrap();
+    let m = router.find_route(Verb::Get, "/search", None).unwrap();
+    assert_eq!(
+        m.route().get_ext::<RateLimit>(),
+        Some(&RateLimit { per_minute: 30 })
+    );
+    assert_eq!(m.route().get_ext::<String>(), None);
+    let mut route = router
+        .find_route(Verb::Get, "/other", None)
+        .unwrap()
+        .route()
+        .clone();
+    assert_eq!(route.get_ext::<RateLimit>(), None);
+    route.insert_ext(RateLimit { per_minute: 5 });
+    route.insert_ext(RateLimit { per_minute: 10 });
+    assert_eq!(route.get_ext(), Some(&RateLimit { per_minute: 10 }));
+}
+
+#[test]
+pub fn test_domain_specificity_table() {
+    fn route_for(domain: &'static str) -> Endpoint {
+        match domain {
+            "api.example.com" => variant_a,
+            "*.example.com" => variant_b,
+            "*.eu.example.com" => me,
+            "**.example.com" => apex,
+            "**.eu.example.com" => subdomain,
+            _ => target,
+        }
+    }
+    let domains = [
+        "**.example.com",
+        "*.example.com",
+        "**.eu.example.com",
+        "api.example.com",
+        "*.eu.example.com",
+    ];
+    let mut router = Router::new();
+    router.path("/").route(target).unwrap();
+    for domain in domains.iter() {
+        router
+            .path("/")
+            .domain(domain)
+            .route(route_for(domain))
+            .unwrap();
+    }
+    let table = [
+        ("api.example.com", "api.example.com"),
+        ("API.example.com", "api.example.com"),
+        ("www.example.com", "*.example.com"),
+        ("api.eu.example.com", "*.eu.example.com"),
+        ("a.b.eu.example.com", "**.eu.example.com"),
+        ("a.b.example.com", "**.example.com"),
+        ("example.com", "-"),
+        ("other.com", "-"),
+    ];
+    for (host, expected) in table.iter() {
+        let found = router.find(Verb::Get, "/", Some(host)).unwrap();
+        assert!(
+            found as usize == route_for(expected) as usize,
+            "{} should select {}",
+            host,
+            expected
+        );
+    }
+    let specificity = |domain| {
+        router
+            .routes()
+            .find(|route| route.domain.as_deref() == domain)
+            .unwrap()
+            .domain_specificity()
+    };
+    assert!(specificity(Some("api.example.com")) > specificity(Some("*.eu.example.com")));
+    assert!(specificity(Some("*.eu.example.com")) > specificity(Some("*.example.com")));
+    assert!(specificity(Some("*.example.com")) > specificity(Some("**.eu.example.com")));
+    assert!(specificity(Some("**.eu.example.com")) > specificity(Some("**.example.com")));
+    assert!(specificity(Some("**.example.com")) > specificity(None));
+}
+
+#[test]
+pub fn test_inject_full_path() {
+    let mut router = Router::new();
+    router.path("/users/:id").route(target).unwrap();
+    let m = router.find_route(Verb::Get, "/users/5", None).unwrap();
+    assert_eq!(m.params().get("__path"), None);
+    router.set_inject_full_path(true);
+    router.set_strip_prefix("/app");
+    let m = router
+        .find_route(Verb::Get, "/app/users/5?x=1", None)
+        .unwrap();
+    assert_eq!(
+        m.params()["__path"],
+        UrlParam::String("/app/users/5".to_string())
+    );
+    assert_eq!(m.params()["id"], UrlParam::Int(5));
+    assert_eq!(
+        router.route(None, Verb::Get, "/files/#__path", target),
+        Err(RouteError::ReservedParam)
+    );
+}
+
+#[test]
+pub fn test_has_verb() {
+    let mut router = Router::new();
+    assert!(!router.has_verb(&Verb::Get));
+    router.path("/a").route(target).unwrap();
+    router.path("/b").patch().route(target).unwrap();
+    assert!(router.has_verb(&Verb::Get));
+    assert!(router.has_verb(&Verb::Patch));
+    assert!(!router.has_verb(&Verb::Delete));
+}
+
+fn echo_name(_url: &UrlParams, _get: &VerbParams, post: &VerbParams) -> Render {
+    match post.get("name") {
+        Some(VerbParam::String(name)) => Render::Plain(name.clone()),
+        _ => Render::Plain("anonymous".to_string()),
+    }
+}
+
+#[test]
+pub fn test_max_body() {
+    let mut router = Router::new();
+    router
+        .path("/small")
+        .post()
+        .max_body(10)
+        .route(echo_name)
+        .unwrap();
+    router
+        .path("/big")
+        .post()
+        .meta("max_body", "3")
+        .route(echo_name)
+        .unwrap();
+    let limits: Vec<Option<usize>> = router.routes().map(|route| route.max_body).collect();
+    assert!(limits.contains(&Some(10)) && limits.contains(&None));
+    let dispatch = |router: &Router, path, body: &str| {
+        router.dispatch_with_body(Verb::Post, path, None, body.as_bytes())
+    };
+    assert_eq!(plain_body(dispatch(&router, "/small", "name=sam")), "sam");
+    assert_eq!(
+        status_parts(dispatch(&router, "/small", "name=samantha")).0,
+        413
+    );
+    assert_eq!(
+        plain_body(dispatch(&router, "/big", "name=samantha")),
+        "samantha"
+    );
+    router.set_max_body(5);
+    assert_eq!(
+        status_parts(dispatch(&router, "/big", "name=samantha")).0,
+        413
+    );
+    assert_eq!(plain_body(dispatch(&router, "/small", "name=sam")), "sam");
+    router.set_too_large_handler(me);
+    assert_eq!(plain_body(dispatch(&router, "/big", "name=samantha")), "me");
+}
+
+#[test]
+pub fn test_head_served_by_get_route() {
+    let mut router = Router::new();
+    router.path("/hello").route(target).unwrap();
+    router.path("/both").route(target).unwrap();
+    router.path("/both").verb(Verb::Head).route(me).unwrap();
+    assert!(router.find(Verb::Head, "/hello", None).is_some());
+    assert!(router.find(Verb::Head, "/missing", None).is_none());
+    assert_eq!(plain_body(router.dispatch(Verb::Head, "/both", None)), "me");
+    let mut out = Vec::new();
+    router
+        .respond(Verb::Head, "/hello", None, &mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 14\r\n\r\n"
+    );
+    let mut out = Vec::new();
+    router.respond(Verb::Get, "/hello", None, &mut out).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .ends_with("Content-Length: 14\r\n\r\nthis is a test"));
+}
+
+#[test]
+pub fn test_resolution_order() {
+    let mut router = Router::new();
+    router.path("/files/*rest").route(target).unwrap();
+    router.path("/files/#name").route(target).unwrap();
+    router.path("/files/readme").route(target).unwrap();
+    router.path("/other").route(target).unwrap();
+    let order = |router: &Router| -> Vec<String> {
+        router
+            .resolution_order("/files/readme", Verb::Get, None)
+            .iter()
+            .map(|route| route.to_path_string())
+            .collect()
+    };
+    assert_eq!(
+        order(&router),
+        ["/files/readme", "/files/#name", "/files/*rest"]
+    );
+    router.path("/#dir/#name").priority(1).route(me).unwrap();
+    assert_eq!(
+        order(&router),
+        [
+            "/#dir/#name",
+            "/files/readme",
+            "/files/#name",
+            "/files/*rest"
+        ]
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/files/readme", None)),
+        "me"
+    );
+    assert!(router
+        .resolution_order("/nothing", Verb::Get, None)
+        .is_empty());
+}
+
+#[test]
+pub fn test_strict_query() {
+    let mut router = Router::new();
+    router
+        .path("/list?limit=:int=20")
+        .route(echo_limit)
+        .unwrap();
+    router
+        .path("/strict?limit=:int=20")
+        .strict_query()
+        .route(echo_limit)
+        .unwrap();
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/list?limit=5&debug=1", None)),
+        "Some(Int(5))"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/strict?limit=5", None)),
+        "Some(Int(5))"
+    );
+    assert_eq!(
+        status_parts(router.dispatch(Verb::Get, "/strict?limit=5&debug=1", None)),
+        (
+            400,
+            "unknown query param: debug".to_string(),
+            "text/plain; charset=utf-8".to_string()
+        )
+    );
+}
+
+#[test]
+pub fn test_find_matches_typed_segments() {
+    let mut router = Router::new();
+    router.path("/goodbye/:id").delete().route(target).unwrap();
+    router.path("/scores/;score").route(target).unwrap();
+    router.path("/names/#name").route(target).unwrap();
+    router.path("/names/admin").route(me).unwrap();
+    assert!(router.find(Verb::Delete, "/goodbye/33", None).is_some());
+    assert!(router.find(Verb::Delete, "/goodbye/-33", None).is_some());
+    assert!(router
+        .find(Verb::Delete, "/goodbye/notanint", None)
+        .is_none());
+    assert!(router.find(Verb::Delete, "/goodbye/3.5", None).is_none());
+    assert!(router
+        .find(Verb::Delete, "/goodbye/33/more", None)
+        .is_none());
+    assert!(router.find(Verb::Delete, "/goodbye", None).is_none());
+    assert!(router.find(Verb::Get, "/scores/3.5", None).is_some());
+    assert!(router.find(Verb::Get, "/scores/3", None).is_some());
+    assert!(router.find(Verb::Get, "/scores/high", None).is_none());
+    assert!(router.find(Verb::Get, "/names/sam", None).is_some());
+    let admin = router.find(Verb::Get, "/names/admin", None).unwrap();
+    assert_eq!(admin as usize, me as Endpoint as usize);
+}
+
+#[test]
+pub fn test_fold() {
+    let mut router = Router::new();
+    router.path("/").route(target).unwrap();
+    router.path("/users/:id").route(target).unwrap();
+    router.path("/users/:id/posts/#slug").route(target).unwrap();
+    router.path("/files/*rest").post().route(target).unwrap();
+    let dynamic = router.fold(0, |total, key, _| {
+        total + key.parts.iter().filter(|part| part.is_dynamic()).count()
+    });
+    assert_eq!(dynamic, 4);
+    let posts = router.fold(0, |total, _, route| {
+        total + (route.verb == Verb::Post) as usize
+    });
+    assert_eq!(posts, 1);
+}
+
+#[test]
+pub fn test_find_with_params() {
+    let mut router = Router::new();
+    router.path("/goodbye/:id").delete().route(target).unwrap();
+    router.path("/scores/;score/#name").route(target).unwrap();
+    let (_, params) = router
+        .find_with_params(Verb::Delete, "/goodbye/33", None)
+        .unwrap();
+    assert_eq!(params["id"], UrlParam::Int(33));
+    let (_, params) = router
+        .find_with_params(Verb::Get, "/scores/9.5/sam", None)
+        .unwrap();
+    assert_eq!(params["score"], UrlParam::Float(9.5));
+    assert_eq!(params["name"], UrlParam::String("sam".to_string()));
+    assert!(router
+        .find_with_params(Verb::Delete, "/goodbye/thirty", None)
+        .is_none());
+}
+
+#[test]
+pub fn test_route_tree_lookup() {
+    let mut router = Router::new();
+    router.path("/users/#name").route(target).unwrap();
+    router.path("/users/me").route(me).unwrap();
+    router.path("/users/:id/posts").route(target).unwrap();
+    router.path("/users/#name/posts").route(target).unwrap();
+    router.path("/users/me/posts").route(me).unwrap();
+    router.path("/feed").format().route(target).unwrap();
+    router.path("/users/*rest").post().route(target).unwrap();
+    assert_eq!(router.routes().len(), 7);
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/users/me", None)),
+        "me"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/users/me/posts", None)),
+        "me"
+    );
+    let template = |path| {
+        router
+            .find_route(Verb::Get, path, None)
+            .map(|m| m.template())
+    };
+    assert_eq!(
+        template("/users/5/posts"),
+        Some("/users/:id/posts".to_string())
+    );
+    assert_eq!(
+        template("/users/sam/posts"),
+        Some("/users/#name/posts".to_string())
+    );
+    assert_eq!(template("/feed.json"), Some("/feed".to_string()));
+    assert_eq!(template("/users/5/posts/extra"), None);
+    assert_eq!(
+        router
+            .find_route(Verb::Post, "/users/a/b/c", None)
+            .map(|m| m.template()),
+        Some("/users/*rest".to_string())
+    );
+}
+
+#[test]
+pub fn test_base64url_segment() {
+    let mut router = Router::new();
+    router.path("/tokens/$token").route(target).unwrap();
+    let token = |path| {
+        router
+            .find_route(Verb::Get, path, None)
+            .map(|m| m.params()["token"].clone())
+    };
+    assert_eq!(
+        token("/tokens/aGVsbG8"),
+        Some(UrlParam::Bytes(b"hello".to_vec()))
+    );
+    assert_eq!(
+        token("/tokens/aGVsbG8="),
+        Some(UrlParam::Bytes(b"hello".to_vec()))
+    );
+    assert_eq!(
+        token("/tokens/-_8"),
+        Some(UrlParam::Bytes(vec![0xfb, 0xff]))
+    );
+    assert_eq!(token("/tokens/aGV+bG8"), None);
+    assert_eq!(token("/tokens/aGVsb"), None);
+}
+
+#[test]
+pub fn test_domain_regexes_compiled_lazily() {
+    let mut router = Router::new();
+    router.path("/a").route(target).unwrap();
+    router.path("/b/:id").post().route(target).unwrap();
+    router.find(Verb::Get, "/a", Some("example.com")).unwrap();
+    assert!(!router.domain_regexes_compiled());
+    router
+        .path("/c")
+        .domain("example.com")
+        .route(target)
+        .unwrap();
+    assert!(router.domain_regexes_compiled());
+
+    fn internal_only(domain: &str) -> bool {
+        domain.ends_with(".internal")
+    }
+    let mut router = Router::new();
+    router.set_domain_validator(internal_only);
+    router
+        .path("/")
+        .domain("API.internal")
+        .route(target)
+        .unwrap();
+    assert_eq!(
+        router.route(Some("example.com"), Verb::Get, "/", target),
+        Err(RouteError::InvalidDomain)
+    );
+    assert!(router.find(Verb::Get, "/", Some("api.internal")).is_some());
+    assert!(!router.domain_regexes_compiled());
+}
+
+#[test]
+pub fn test_catch_all_wildcard() {
+    let mut router = Router::new();
+    router.path("/static/*path").route(target).unwrap();
+    let m = router
+        .find_route(Verb::Get, "/static/css/site.css", None)
+        .unwrap();
+    assert_eq!(
+        m.params()["path"],
+        UrlParam::String("css/site.css".to_string())
+    );
+    assert_eq!(
+        router.routes().next().unwrap().vars,
+        vec![RouteVar::Wildcard("path")]
+    );
+    let err = router
+        .route(None, Verb::Get, "/static/*path/more", target)
+        .unwrap_err();
+    assert_eq!(err, RouteError::WildcardNotLast);
+    assert_eq!(err.to_string(), "wildcard must be the last segment");
+    assert_eq!(
+        RouteKey::from_path(Verb::Get, "/*a/b"),
+        Err(RouteError::WildcardNotLast)
+    );
+    assert_eq!(
+        router.route(None, Verb::Get, "/static/*path/", target),
+        Err(RouteError::DuplicateRoute)
+    );
+    assert_eq!(
+        router.route(None, Verb::Get, "/bad path/*x", target),
+        Err(RouteError::InvalidPathFormat)
+    );
+}
+
+fn require_token(_url: &UrlParams, get: &VerbParams, _post: &VerbParams) -> Option<Render> {
+    match get.get("token") {
+        Some(_) => None,
+        None => Some(Render::Status {
+            code: 401,
+            body: "Unauthorized".to_string(),
+            content_type: "text/plain; charset=utf-8".to_string(),
+        }),
+    }
+}
+
+#[test]
+pub fn test_scoped_middleware() {
+    let mut router = Router::new();
+    router.path("/admin/*rest").route(target).unwrap();
+    router.path("/administrator").route(target).unwrap();
+    router.path("/public").route(target).unwrap();
+    router.path("/account").tag("auth").route(target).unwrap();
+    router
+        .path("/")
+        .domain("internal.example.com")
+        .route(target)
+        .unwrap();
+    router.use_middleware_for(Scope::Prefix("/admin"), require_token);
+    router.use_middleware_for(Scope::Tag("auth"), require_token);
+    router.use_middleware_for(Scope::Domain("internal.example.com"), require_token);
+    let code = |path, domain| status_parts(router.dispatch(Verb::Get, path, domain)).0;
+    assert_eq!(code("/admin/users", None), 401);
+    assert_eq!(code("/account", None), 401);
+    assert_eq!(code("/", Some("internal.example.com")), 401);
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/admin/users?token=x", None)),
+        "this is a test"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/public", None)),
+        "this is a test"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/administrator", None)),
+        "this is a test"
+    );
+}
+
+#[test]
+pub fn test_optional_trailing_segment() {
+    let mut router = Router::new();
+    router.path("/archive/:year/:month?").route(target).unwrap();
+    router
+        .path("/posts/#slug??page=:int=1")
+        .route(echo_limit)
+        .unwrap();
+    let archive = |path| router.find_with_params(Verb::Get, path, None);
+    let (with_month, params) = archive("/archive/2024/06").unwrap();
+    assert_eq!(params["year"], UrlParam::Int(2024));
+    assert_eq!(params["month"], UrlParam::Int(6));
+    let (without_month, params) = archive("/archive/2024").unwrap();
+    assert_eq!(with_month as usize, without_month as usize);
+    assert_eq!(params["year"], UrlParam::Int(2024));
+    assert_eq!(params.get("month"), None);
+    assert!(archive("/archive").is_none());
+    assert!(archive("/archive/2024/june").is_none());
+    assert!(archive("/archive/2024/06/01").is_none());
+    let m = router.find_route(Verb::Get, "/archive/2024", None).unwrap();
+    assert_eq!(m.template(), "/archive/:year/:month?");
+    assert!(router.find(Verb::Get, "/posts", None).is_some());
+    assert!(router
+        .find(Verb::Get, "/posts/hello?page=2", None)
+        .is_some());
+    assert_eq!(
+        router.route(None, Verb::Get, "/archive/:year/:month", target),
+        Err(RouteError::DuplicateRoute)
+    );
+    assert_eq!(
+        router.route(None, Verb::Get, "/static/latest?", target),
+        Err(RouteError::InvalidPathFormat)
+    );
+}
+
+#[test]
+pub fn test_routes_for() {
+    let mut router = Router::new();
+    router.path("/").route(target).unwrap();
+    router
+        .path("/docs")
+        .domain("docs.example.com")
+        .route(target)
+        .unwrap();
+    router
+        .path("/tenant")
+        .domain("*.example.com")
+        .route(target)
+        .unwrap();
+    router
+        .path("/upload")
+        .domain("docs.example.com")
+        .post()
+        .route(target)
+        .unwrap();
+    router
+        .path("/other")
+        .domain("other.com")
+        .route(target)
+        .unwrap();
+    let paths = |verb, domain| -> Vec<String> {
+        router
+            .routes_for(&verb, domain)
+            .iter()
+            .map(|route| route.to_path_string())
+            .collect()
+    };
+    assert_eq!(
+        paths(Verb::Get, Some("docs.example.com")),
+        ["/", "/docs", "/tenant"]
+    );
+    assert_eq!(paths(Verb::Post, Some("docs.example.com")), ["/upload"]);
+    assert_eq!(paths(Verb::Get, None), ["/"]);
+}
+
+#[test]
+pub fn test_regex_constrained_vars() {
+    let mut router = Router::new();
+    router.path(r"/files/#name<\d{4}>").route(target).unwrap();
+    router.path("/slugs/#slug<[a-z0-9-]+>").route(me).unwrap();
+    router.path("/slugs/#other").route(target).unwrap();
+    router
+        .path("/colors/#color<colou?r-[a-z]+>")
+        .route(target)
+        .unwrap();
+    let m = router.find_route(Verb::Get, "/files/1234", None).unwrap();
+    assert_eq!(m.params()["name"], UrlParam::String("1234".to_string()));
+    assert_eq!(m.template(), r"/files/#name<\d{4}>");
+    assert!(router.find(Verb::Get, "/files/abcd", None).is_none());
+    assert!(router.find(Verb::Get, "/files/12345", None).is_none());
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/slugs/hello-world", None)),
+        "me"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/slugs/Hello_World", None)),
+        "this is a test"
+    );
+    assert!(router.find(Verb::Get, "/colors/color-red", None).is_some());
+    assert!(router.find(Verb::Get, "/colors/colour-red", None).is_some());
+    let err = router
+        .route(None, Verb::Get, "/bad/#name<[a-z>", target)
+        .unwrap_err();
+    assert_eq!(err, RouteError::InvalidConstraint);
+    assert_eq!(err.to_string(), "invalid route constraint regex");
+}
+
+#[test]
+pub fn test_wildcard_domain_resolution() {
+    let mut router = Router::new();
+    router
+        .path("/status")
+        .domain("*.staging.mysite.com")
+        .route(subdomain)
+        .unwrap();
+    router
+        .path("/status")
+        .domain("admin.staging.mysite.com")
+        .route(me)
+        .unwrap();
+    let found = |host| router.find(Verb::Get, "/status", Some(host));
+    assert_eq!(
+        found("api.staging.mysite.com").unwrap() as usize,
+        subdomain as Endpoint as usize
+    );
+    assert!(found("a.b.staging.mysite.com").is_none());
+    assert!(found("staging.mysite.com").is_none());
+    assert_eq!(
+        found("admin.staging.mysite.com").unwrap() as usize,
+        me as Endpoint as usize
+    );
+}
+
+#[test]
+pub fn test_matched_depth() {
+    let mut router = Router::new();
+    router.path("/files/*rest").route(target).unwrap();
+    router.path("/browse/#root/+dirs").route(target).unwrap();
+    router.path("/users/:id").route(target).unwrap();
+    let depth = |path| {
+        router
+            .find_route(Verb::Get, path, None)
+            .unwrap()
+            .matched_depth()
+    };
+    assert_eq!(depth("/files/a/b/c"), 1);
+    assert_eq!(depth("/files"), 1);
+    assert_eq!(depth("/browse/home/a/b"), 2);
+    assert_eq!(depth("/users/5"), 2);
+}
+
+#[test]
+pub fn test_try_find() {
+    let mut router = Router::new();
+    router.path("/users/:id").route(target).unwrap();
+    assert!(router
+        .try_find(Verb::Get, "/users/5", Some("example.com:8080"))
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        router
+            .try_find(Verb::Get, "/missing", None)
+            .map(|e| e.is_some()),
+        Ok(false)
+    );
+    assert_eq!(
+        router
+            .try_find(Verb::Get, "users/5", None)
+            .map(|e| e.is_some()),
+        Err(RouteError::InvalidPathFormat)
+    );
+    assert_eq!(
+        router
+            .try_find(Verb::Get, "/users/5 6", None)
+            .map(|e| e.is_some()),
+        Err(RouteError::InvalidPathFormat)
+    );
+    assert_eq!(
+        router
+            .try_find(Verb::Get, "/users/5", Some("bad host"))
+            .map(|e| e.is_some()),
+        Err(RouteError::InvalidDomain)
+    );
+}
+
+#[test]
+pub fn test_case_insensitive_literals() {
+    let mut router = Router::new();
+    router.path("/Contact/:id").route(target).unwrap();
+    router.path("/files/#name").route(target).unwrap();
+    assert!(router.find(Verb::Get, "/contact/5", None).is_none());
+    assert!(router.find(Verb::Get, "/Contact/5", None).is_some());
+    router.set_case_insensitive(true);
+    assert!(router.find(Verb::Get, "/contact/5", None).is_some());
+    assert!(router.find(Verb::Get, "/CONTACT/5", None).is_some());
+    let m = router.find_route(Verb::Get, "/FILES/ReadMe", None).unwrap();
+    assert_eq!(m.params()["name"], UrlParam::String("ReadMe".to_string()));
+    assert_eq!(m.route().to_path_string(), "/files/#name");
+}
+
+#[test]
+pub fn test_find_or_405() {
+    let mut router = Router::new();
+    router.path("/hello/world").post().route(target).unwrap();
+    router.path("/users/:id").route(target).unwrap();
+    router.path("/users/:id").delete().route(target).unwrap();
+    match router.find_or_405(Verb::Get, "/hello/world", None) {
+        FindResult::MethodNotAllowed(verbs) => assert_eq!(verbs, vec![Verb::Post]),
+        other => panic!("expected 405, got {:?}", other),
+    }
+    assert!(matches!(
+        router.find_or_405(Verb::Post, "/hello/world", None),
+        FindResult::Found(_)
+    ));
+    assert!(matches!(
+        router.find_or_405(Verb::Get, "/nowhere", None),
+        FindResult::NotFound
+    ));
+    assert_eq!(
+        router.find_allowed_methods("/users/5", None),
+        vec![Verb::Get, Verb::Delete, Verb::Head]
+    );
+    assert!(matches!(
+        router.find_or_405(Verb::Head, "/users/5", None),
+        FindResult::Found(_)
+    ));
+    assert!(router.find_allowed_methods("/users/five", None).is_empty());
+}
+
+#[test]
+pub fn test_domain_set() {
+    let mut router = Router::new();
+    router
+        .path("/shared")
+        .domains(&["a.com", "B.com"])
+        .route(target)
+        .unwrap();
+    assert_eq!(router.routes().count(), 1);
+    assert!(router.find(Verb::Get, "/shared", Some("a.com")).is_some());
+    assert!(router.find(Verb::Get, "/shared", Some("b.com")).is_some());
+    assert!(router.find(Verb::Get, "/shared", Some("c.com")).is_none());
+    let route = router.routes().next().unwrap();
+    assert_eq!(route.domains(), vec!["a.com", "b.com"]);
+    assert_eq!(
+        router.static_get_urls("https://example.com"),
+        vec!["https://a.com/shared", "https://b.com/shared"]
+    );
+    assert_eq!(
+        router
+            .path("/other")
+            .domains(&["a.com", "c..com"])
+            .route(target),
+        Err(RouteError::InvalidDomain)
+    );
+}
+
+#[test]
+pub fn test_domain_set_is_not_a_joined_string() {
+    let mut router = Router::new();
+    router
+        .path("/mixed")
+        .domains(&["a.com", "*.b.com"])
+        .route(target)
+        .unwrap();
+    router
+        .path("/flipped")
+        .domains(&["*.a.com", "b.com"])
+        .route(target)
+        .unwrap();
+    router
+        .path("/wild")
+        .domains(&["*.a.com", "*.b.com"])
+        .route(target)
+        .unwrap();
+    let wildcard_only: Vec<String> = router
+        .wildcard_only_routes()
+        .into_iter()
+        .map(|route| route.to_path_string())
+        .collect();
+    assert_eq!(wildcard_only, vec!["/wild"]);
+    assert!(router.to_snapshot().contains("GET {*.a.com,*.b.com} /wild"));
+    assert!(router
+        .to_manifest()
+        .contains("\"domain\":[\"*.a.com\",\"*.b.com\"],\"path\":\"/wild\""));
+
+    // a validator allowing commas can't turn one domain into a set
+    let mut router = Router::new();
+    router.set_domain_validator(|_| true);
+    router
+        .route(Some("a.com,evil.com"), Verb::Get, "/", target)
+        .unwrap();
+    assert!(router.find(Verb::Get, "/", Some("evil.com")).is_none());
+    assert!(router.find(Verb::Get, "/", Some("a.com")).is_none());
+    assert!(router
+        .find(Verb::Get, "/", Some("a.com,evil.com"))
+        .is_some());
+}
+
+fn not_found(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("nothing here".to_string())
+}
+
+fn echo_id(url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain(format!("{:?}", url.get("id")))
+}
+
+#[test]
+pub fn test_dispatch_fallback() {
+    let mut router = Router::new();
+    router.path("/users/:id").route(echo_id).unwrap();
+    assert_eq!(
+        status_parts(router.dispatch(Verb::Get, "/nope", None)),
+        (
+            404,
+            "Not Found".to_string(),
+            "text/plain; charset=utf-8".to_string()
+        )
+    );
+    router.set_fallback(not_found);
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/nope", None)),
+        "nothing here"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/users/7", None)),
+        "Some(Int(7))"
+    );
+}
+
+#[test]
+pub fn test_route_summary_in_manifest() {
+    let mut router = Router::new();
+    router
+        .path("/users/:id")
+        .summary("Get a user by id")
+        .description("Looks up a \"user\"")
+        .route(target)
+        .unwrap();
+    router
+        .path("/health")
+        .domain("example.com")
+        .route(target)
+        .unwrap();
+    let route = router
+        .find_route(Verb::Get, "/users/5", None)
+        .unwrap()
+        .route();
+    assert_eq!(route.summary(), Some("Get a user by id"));
+    assert_eq!(
+        router.to_manifest(),
+        concat!(
+            "[{\"verb\":\"GET\",\"domain\":null,\"path\":\"/users/:id\",",
+            "\"summary\":\"Get a user by id\",\"description\":\"Looks up a \\\"user\\\"\"},",
+            "{\"verb\":\"GET\",\"domain\":\"example.com\",\"path\":\"/health\",",
+            "\"summary\":null,\"description\":null}]"
+        )
+    );
+}
+
+fn cached(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain("cached".to_string()).with_header("Cache-Control", "max-age=60")
+}
+
+#[test]
+pub fn test_default_headers() {
+    let mut router = Router::new();
+    router.set_default_headers(vec![
+        ("X-Content-Type-Options".to_string(), "nosniff".to_string()),
+        ("Cache-Control".to_string(), "no-store".to_string()),
+    ]);
+    router
+        .path("/report")
+        .header("X-Frame-Options", "DENY")
+        .header("Cache-Control", "private")
+        .route(target)
+        .unwrap();
+    router.path("/cached").route(cached).unwrap();
+    let render = router.dispatch(Verb::Get, "/report", None);
+    assert_eq!(render.header("x-content-type-options"), Some("nosniff"));
+    assert_eq!(render.header("X-Frame-Options"), Some("DENY"));
+    assert_eq!(render.header("Cache-Control"), Some("private"));
+    let render = router.dispatch(Verb::Get, "/cached", None);
+    assert_eq!(render.header("Cache-Control"), Some("max-age=60"));
+    let render = router.dispatch(Verb::Get, "/missing", None);
+    assert_eq!(render.header("X-Content-Type-Options"), Some("nosniff"));
+    let mut out = Vec::new();
+    router
+        .dispatch(Verb::Get, "/report", None)
+        .write_http(&mut out)
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("\r\nX-Frame-Options: DENY\r\n"));
+    assert!(out.ends_with("\r\n\r\nthis is a test"));
+}
+
+#[test]
+pub fn test_check_conflicts() {
+    let mut router = Router::new();
+    router.path("/users/:id").route(target).unwrap();
+    router.path("/users/#name").route(target).unwrap();
+    router.path("/users/me").route(target).unwrap();
+    router.path("/users/;score").post().route(target).unwrap();
+    router.path("/events/@day").route(target).unwrap();
+    router.path("/events/:id").route(target).unwrap();
+    let conflicts: Vec<(String, String)> = router
+        .check_conflicts()
+        .into_iter()
+        .map(|(a, b)| (format!("{:?}", a.parts()), format!("{:?}", b.parts())))
+        .collect();
+    assert_eq!(
+        conflicts,
+        vec![
+            (
+                "[Path(\"users\"), Int]".to_string(),
+                "[Path(\"users\"), String]".to_string()
+            ),
+            (
+                "[Path(\"users\"), String]".to_string(),
+                "[Path(\"users\"), Path(\"me\")]".to_string()
+            ),
+        ]
+    );
+    let mut strict = Router::new();
+    strict.set_reject_conflicts(true);
+    strict.path("/users/:id").route(target).unwrap();
+    assert_eq!(
+        strict.path("/users/#name").route(target),
+        Err(RouteError::AmbiguousRoute)
+    );
+    assert_eq!(
+        strict.path("/users/42").route(target),
+        Err(RouteError::AmbiguousRoute)
+    );
+    assert_eq!(strict.path("/users/me").route(target), Ok(()));
+    assert_eq!(strict.path("/users/:id").post().route(target), Ok(()));
+}
+
+#[test]
+pub fn test_bool_vars() {
+    let mut router = Router::new();
+    router.path("/feature/!enabled").route(target).unwrap();
+    let m = router.find_route(Verb::Get, "/feature/true", None).unwrap();
+    assert_eq!(m.params()["enabled"], UrlParam::Bool(true));
+    assert_eq!(m.route().to_path_string(), "/feature/!enabled");
+    let m = router.find_route(Verb::Get, "/feature/0", None).unwrap();
+    assert_eq!(m.params()["enabled"], UrlParam::Bool(false));
+    assert!(router.find(Verb::Get, "/feature/maybe", None).is_none());
+    // bools are narrower than ints, so they win for `1` and `0`
+    router.path("/feature/:id").route(target).unwrap();
+    let m = router.find_route(Verb::Get, "/feature/1", None).unwrap();
+    assert_eq!(m.params()["enabled"], UrlParam::Bool(true));
+}
+
+#[test]
+pub fn test_catch_all_ext() {
+    let mut router = Router::new();
+    router.path("/static/*rest").route(target).unwrap();
+    let m = router
+        .find_route(Verb::Get, "/static/img/logo.png", None)
+        .unwrap();
+    assert_eq!(
+        m.params()["rest"],
+        UrlParam::String("img/logo.png".to_string())
+    );
+    assert_eq!(m.params()[EXT_PARAM], UrlParam::String("png".to_string()));
+    let m = router
+        .find_route(Verb::Get, "/static/img/LICENSE", None)
+        .unwrap();
+    assert_eq!(
+        m.params()["rest"],
+        UrlParam::String("img/LICENSE".to_string())
+    );
+    assert_eq!(m.params().get(EXT_PARAM), None);
+    let m = router
+        .find_route(Verb::Get, "/static/v1.2/.env", None)
+        .unwrap();
+    assert_eq!(m.params().get(EXT_PARAM), None);
+}
+
+#[test]
+pub fn test_path_shapes() {
+    let mut router = Router::new();
+    router.path("/users/:id").route(target).unwrap();
+    router.path("/users/:uid").post().route(target).unwrap();
+    router
+        .path("/users/:id")
+        .domain("example.com")
+        .route(target)
+        .unwrap();
+    router.path("/about").route(target).unwrap();
+    let shapes = router.path_shapes();
+    assert_eq!(shapes.len(), 2);
+    assert_eq!(
+        shapes[&vec![RoutePart::Path("users".to_string()), RoutePart::Int]],
+        vec![Verb::Get, Verb::Post]
+    );
+    assert_eq!(
+        shapes[&vec![RoutePart::Path("about".to_string())]],
+        vec![Verb::Get]
+    );
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+pub fn test_uuid_vars() {
+    let mut router = Router::new();
+    router.path("/orgs/^oid").route(target).unwrap();
+    let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+    let m = router
+        .find_route(Verb::Get, &format!("/orgs/{}", id), None)
+        .unwrap();
+    assert_eq!(
+        m.params()["oid"],
+        UrlParam::Uuid(uuid::Uuid::parse_str(id).unwrap())
+    );
+    match &m.params()["oid"] {
+        UrlParam::Uuid(uuid) => assert_eq!(uuid.to_string(), id),
+        other => panic!("expected a uuid, got {:?}", other),
+    }
+    assert_eq!(m.route().to_path_string(), "/orgs/^oid");
+    assert!(router.find(Verb::Get, "/orgs/not-a-uuid", None).is_none());
+    assert!(router
+        .find(Verb::Get, "/orgs/67e5504410b1426f9247bb680e5fe0c8", None)
+        .is_none());
+}
+
+#[test]
+pub fn test_url_params_typed_getters() {
+    let mut router = Router::new();
+    router
+        .path("/items/:id/;weight/#name/!visible")
+        .route(target)
+        .unwrap();
+    let params = router
+        .find_route(Verb::Get, "/items/5/2.5/widget/true", None)
+        .unwrap()
+        .into_params();
+    assert_eq!(params.get_int("id"), Some(5));
+    assert_eq!(params.get_float("weight"), Some(2.5));
+    assert_eq!(params.get_str("name"), Some("widget"));
+    assert_eq!(params.get_bool("visible"), Some(true));
+    assert_eq!(params.get_int("name"), None);
+    assert_eq!(params.get_str("id"), None);
+    assert_eq!(params.get_float("id"), None);
+    assert_eq!(params.get_bool("weight"), None);
+    assert_eq!(params.get_int("missing"), None);
+    assert_eq!(params.get_str("missing"), None);
+    assert_eq!(params["id"], UrlParam::Int(5));
+}
+
+#[test]
+pub fn test_dispatch_parts() {
+    let mut router = Router::new();
+    router.path("/users/:id").route(echo_id).unwrap();
+    router
+        .path("/limit")
+        .domain("example.com")
+        .route(echo_limit)
+        .unwrap();
+    let parts = router
+        .dispatch_parts(
+            Verb::Get,
+            Some("example.com:8443"),
+            "/limit",
+            "limit=7",
+            &[],
+        )
+        .unwrap();
+    let url = router
+        .dispatch_url(Verb::Get, "https://example.com:8443/limit?limit=7", &[])
+        .unwrap();
+    assert_eq!(plain_body(parts), plain_body(url));
+    assert_eq!(
+        plain_body(router.dispatch_url(Verb::Get, "/users/3", &[]).unwrap()),
+        "Some(Int(3))"
+    );
+    assert_eq!(
+        plain_body(
+            router
+                .dispatch_parts(Verb::Get, None, "/users/3", "", &[])
+                .unwrap()
+        ),
+        "Some(Int(3))"
+    );
+    assert!(router
+        .dispatch_url(Verb::Get, "https://other.com/limit", &[])
+        .is_none());
+    router.set_fallback(not_found);
+    assert_eq!(
+        plain_body(
+            router
+                .dispatch_url(Verb::Get, "https://other.com/limit", &[])
+                .unwrap()
+        ),
+        "nothing here"
+    );
+}
+
+#[test]
+pub fn test_from_query_percent_decoding() {
+    let params = VerbParams::from_query("a=1&b=hello%20world&c");
+    assert_eq!(params.get("a"), Some(&VerbParam::String("1".to_string())));
+    assert_eq!(
+        params.get("b"),
+        Some(&VerbParam::String("hello world".to_string()))
+    );
+    assert_eq!(params.get("c"), Some(&VerbParam::String(String::new())));
+    let params = VerbParams::from_query("na%6De=%E2%9C%93&bad=100%&odd=%+1");
+    assert_eq!(
+        params.get("name"),
+        Some(&VerbParam::String("✓".to_string()))
+    );
+    assert_eq!(
+        params.get("bad"),
+        Some(&VerbParam::String("100%".to_string()))
+    );
+    assert_eq!(
+        params.get("odd"),
+        Some(&VerbParam::String("%+1".to_string()))
+    );
+    assert_eq!(VerbParams::from_query(""), VerbParams::new());
+}
+
+fn redirect_location(render: Render) -> (String, bool) {
+    match render {
+        Render::Redirect {
+            location,
+            permanent,
+        } => (location, permanent),
+        _ => panic!("expected a redirect"),
+    }
+}
+
+#[test]
+pub fn test_canonical_redirect() {
+    let mut router = Router::new();
+    router.set_case_insensitive(true);
+    router.path("/users").route(target).unwrap();
+    router.path("/users/:id/Posts").route(echo_id).unwrap();
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/Users/", None)),
+        "this is a test"
+    );
+    router.set_canonical_redirect(true);
+    assert_eq!(
+        redirect_location(router.dispatch(Verb::Get, "/Users/", None)),
+        ("/users".to_string(), true)
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/users", None)),
+        "this is a test"
+    );
+    assert_eq!(
+        redirect_location(router.dispatch(Verb::Get, "//USERS/5/posts?page=2", None)),
+        ("/users/5/Posts?page=2".to_string(), true)
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/users/5/Posts", None)),
+        "Some(Int(5))"
+    );
+    let mut out = Vec::new();
+    router
+        .dispatch(Verb::Get, "/users/", None)
+        .write_http(&mut out)
+        .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+    assert!(out.contains("\r\nLocation: /users\r\n"));
+}
+
+#[test]
+pub fn test_from_form() {
+    let params = VerbParams::from_form("name=Sam&age=30");
+    assert_eq!(
+        params.get("name"),
+        Some(&VerbParam::String("Sam".to_string()))
+    );
+    assert_eq!(
+        params.get("age"),
+        Some(&VerbParam::String("30".to_string()))
+    );
+    let params = VerbParams::from_form("full+name=Sam+Johnson&sum=1%2B1&name=a&name=b");
+    assert_eq!(
+        params.get("full name"),
+        Some(&VerbParam::String("Sam Johnson".to_string()))
+    );
+    assert_eq!(
+        params.get("sum"),
+        Some(&VerbParam::String("1+1".to_string()))
+    );
+    assert_eq!(
+        params.get("name"),
+        Some(&VerbParam::String("a".to_string()))
+    );
+    assert_eq!(
+        VerbParams::from_query("q=a+b").get("q"),
+        Some(&VerbParam::String("a+b".to_string()))
+    );
+    let mut router = Router::new();
+    router.path("/greet").post().route(echo_name).unwrap();
+    assert_eq!(
+        plain_body(router.dispatch_with_body(Verb::Post, "/greet", None, b"name=Sam+J")),
+        "Sam J"
+    );
+}
+
+#[test]
+pub fn test_route_handles() {
+    let mut router = Router::new();
+    let users = router
+        .register(None, Verb::Get, "/users/:id", echo_id)
+        .unwrap();
+    let about = router.register(None, Verb::Get, "/about", target).unwrap();
+    router
+        .route(None, Verb::Get, "/members/#name", target)
+        .unwrap();
+    assert_eq!(
+        router.get_by_handle(users).unwrap().to_path_string(),
+        "/users/:id"
+    );
+    let mut users_route = router.get_mut_by_handle(users).unwrap();
+    users_route.set_meta("owner", "accounts");
+    users_route.set_summary("a single user");
+    assert_eq!(users_route.route().summary(), Some("a single user"));
+    assert_eq!(
+        router
+            .find_route(Verb::Get, "/users/5", None)
+            .unwrap()
+            .route()
+            .meta("owner"),
+        Some("accounts")
+    );
+    router.rename_by_handle(users, "/people/:id").unwrap();
+    assert!(router.find(Verb::Get, "/users/5", None).is_none());
+    let m = router.find_route(Verb::Get, "/people/5", None).unwrap();
+    assert_eq!(m.route().meta("owner"), Some("accounts"));
+    assert_eq!(
+        router.rename_by_handle(users, "/members/#name"),
+        Err(RouteError::DuplicateRoute)
+    );
+    assert_eq!(
+        router.get_by_handle(users).unwrap().to_path_string(),
+        "/people/:id"
+    );
+    let removed = router.remove_by_handle(about).unwrap();
+    assert_eq!(removed.to_path_string(), "/about");
+    assert!(router.find(Verb::Get, "/about", None).is_none());
+    assert!(router.get_by_handle(about).is_none());
+    assert!(router.remove_by_handle(about).is_none());
+    assert_eq!(router.routes().count(), 2);
+}
+
+#[test]
+pub fn test_multi_valued_params() {
+    let params = VerbParams::from_query("tag=a&tag=b&page=2");
+    assert_eq!(
+        params.get_all("tag"),
+        &[
+            VerbParam::String("a".to_string()),
+            VerbParam::String("b".to_string())
+        ]
+    );
+    assert_eq!(params["tag"], VerbParam::String("a".to_string()));
+    assert_eq!(params.get_all("page").len(), 1);
+    assert!(params.get_all("missing").is_empty());
+    let mut params = params;
+    params.add("tag".to_string(), VerbParam::String("c".to_string()));
+    assert_eq!(params.get_all("tag"), &[VerbParam::String("c".to_string())]);
+}
+
+#[test]
+pub fn test_list_vars() {
+    let strings = |values: &[&str]| {
+        UrlParam::Array(
+            values
+                .iter()
+                .map(|value| UrlParam::String(value.to_string()))
+                .collect(),
+        )
+    };
+    let mut router = Router::new();
+    router.path("/items/,colors").route(target).unwrap();
+    router
+        .path("/tags/,tags")
+        .list_delimiter('+')
+        .empty_lists()
+        .route(target)
+        .unwrap();
+    let m = router
+        .find_route(Verb::Get, "/items/red,green,blue", None)
+        .unwrap();
+    assert_eq!(m.params()["colors"], strings(&["red", "green", "blue"]));
+    assert_eq!(m.route().to_path_string(), "/items/,colors");
+    let m = router.find_route(Verb::Get, "/items/red", None).unwrap();
+    assert_eq!(m.params()["colors"], strings(&["red"]));
+    assert!(router.find(Verb::Get, "/items/,", None).is_none());
+    let m = router.find_route(Verb::Get, "/tags/a+b", None).unwrap();
+    assert_eq!(m.params()["tags"], strings(&["a", "b"]));
+    let m = router.find_route(Verb::Get, "/tags/+", None).unwrap();
+    assert_eq!(m.params()["tags"], strings(&[]));
+}
+
+#[test]
+pub fn test_self_check() {
+    let mut router = Router::new();
+    router.path("/users/:id").route(target).unwrap();
+    router.path("/users/me").route(target).unwrap();
+    router.path("/users/!active").route(target).unwrap();
+    router.path("/scores/;score/#name").route(target).unwrap();
+    router
+        .path("/archive/@day")
+        .date_segments()
+        .route(target)
+        .unwrap();
+    router.path("/blobs/$blob").route(target).unwrap();
+    router.path("/colors/,colors").route(target).unwrap();
+    router.path("/files/*rest").route(target).unwrap();
+    router.path("/tree/+nodes").post().route(target).unwrap();
+    router
+        .path("/list?limit=:int&page=:int=1")
+        .route(target)
+        .unwrap();
+    router.path("/archive/:year/:month?").route(target).unwrap();
+    router
+        .path("/api")
+        .domain("*.example.com")
+        .route(target)
+        .unwrap();
+    router
+        .path("/codes/#code<[A-Z]{3}>")
+        .example("code", "ABC")
+        .route(target)
+        .unwrap();
+    assert_eq!(router.self_check(), Ok(()));
+    assert_eq!(
+        router
+            .find_route(Verb::Get, "/users/42", None)
+            .unwrap()
+            .route()
+            .example_path(),
+        "/users/42"
+    );
+    // the default sample can't satisfy this constraint, so the route is never exercised
+    router.path("/zips/#zip<[0-9]{5}>").route(target).unwrap();
+    let failures = router.self_check().unwrap_err();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(
+        failures[0],
+        RouteKey::new(Verb::Get, "/zips/hello", None)
+            .map(|mut key| {
+                key.parts[1] = RoutePart::Regex("[0-9]{5}".to_string());
+                key
+            })
+            .unwrap()
+    );
+}
+
+#[test]
+pub fn test_verb_params_typed_getters() {
+    let mut params = VerbParams::from_query("page=3&ratio=0.5&debug=true&name=sam&bad=3x");
+    params.add("limit".to_string(), VerbParam::Int(20));
+    params.add("strict".to_string(), VerbParam::Bool(false));
+    assert_eq!(params.get_int("page"), Some(3));
+    assert_eq!(params.get_int("limit"), Some(20));
+    assert_eq!(params.get_float("ratio"), Some(0.5));
+    assert_eq!(params.get_float("limit"), Some(20.0));
+    assert_eq!(params.get_bool("debug"), Some(true));
+    assert_eq!(params.get_bool("strict"), Some(false));
+    assert_eq!(params.get_str("name"), Some("sam"));
+    assert_eq!(params.get_int("bad"), None);
+    assert_eq!(params.get_float("name"), None);
+    assert_eq!(params.get_bool("page"), None);
+    assert_eq!(params.get_str("limit"), None);
+    assert_eq!(params.get_int("missing"), None);
+    assert_eq!(params.get_str("missing"), None);
+}
+
+#[test]
+pub fn test_percent_decoded_segments() {
+    let mut router = Router::new();
+    router.path("/café").route(target).unwrap();
+    router.path("/docs/#name").route(target).unwrap();
+    router
+        .path("/files/*rest")
+        .split_wildcard()
+        .route(target)
+        .unwrap();
+    assert!(router.find(Verb::Get, "/caf%C3%A9", None).is_some());
+    assert!(router.find(Verb::Get, "/café", None).is_some());
+    let m = router.find_route(Verb::Get, "/docs/a%2Fb", None).unwrap();
+    assert_eq!(m.params()["name"], UrlParam::String("a/b".to_string()));
+    let m = router
+        .find_route(Verb::Get, "/files/a%2Fb/c", None)
+        .unwrap();
+    assert_eq!(
+        m.params()["rest"],
+        UrlParam::Array(vec![
+            UrlParam::String("a/b".to_string()),
+            UrlParam::String("c".to_string())
+        ])
+    );
+    assert_eq!(percent_decode("plain"), std::borrow::Cow::Borrowed("plain"));
+    assert_eq!(percent_decode("a%20b"), "a b");
+    let mut encoded = Router::new();
+    encoded.path("/caf%C3%A9").route(target).unwrap();
+    assert!(encoded.find(Verb::Get, "/café", None).is_some());
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+pub fn test_from_json() {
+    let params = VerbParams::from_json(r#"{"id":3,"name":"x","ok":true}"#).unwrap();
+    assert_eq!(params.get("id"), Some(&VerbParam::Int(3)));
+    assert_eq!(
+        params.get("name"),
+        Some(&VerbParam::String("x".to_string()))
+    );
+    assert_eq!(params.get("ok"), Some(&VerbParam::Bool(true)));
+    let params = VerbParams::from_json(r#"{"ratio":0.5,"tags":["a"],"meta":{"a":null}}"#).unwrap();
+    assert_eq!(params.get("ratio"), Some(&VerbParam::Float(0.5)));
+    assert_eq!(
+        params.get("tags"),
+        Some(&VerbParam::String("[\"a\"]".to_string()))
+    );
+    assert_eq!(
+        params.get("meta"),
+        Some(&VerbParam::String("{\"a\":null}".to_string()))
+    );
+    assert_eq!(
+        VerbParams::from_json("[1, 2]"),
+        Err("expected a JSON object")
+    );
+    assert_eq!(VerbParams::from_json("{"), Err("invalid JSON"));
+}
+
+#[test]
+pub fn test_verb_params_iteration() {
+    let params = VerbParams::from_query("b=2&a=1&c&a=3");
+    let mut keys: Vec<&str> = params.keys().collect();
+    keys.sort_unstable();
+    assert_eq!(keys, vec!["a", "b", "c"]);
+    let mut pairs: Vec<(&str, &VerbParam)> = params.iter().collect();
+    pairs.sort_by_key(|(key, _)| *key);
+    assert_eq!(
+        pairs,
+        vec![
+            ("a", &VerbParam::String("1".to_string())),
+            ("b", &VerbParam::String("2".to_string())),
+            ("c", &VerbParam::String(String::new())),
+        ]
+    );
+    assert_eq!(params.len(), 3);
+    assert!(!params.is_empty());
+    assert!(params.contains_key("c"));
+    assert!(!params.contains_key("d"));
+    assert!(VerbParams::from_query("").is_empty());
+}
+
+#[test]
+pub fn test_remove_and_clear() {
+    let mut router = Router::new();
+    router
+        .route(None, Verb::Get, "/users/#name", target)
+        .unwrap();
+    let handle = router
+        .register(Some("example.com"), Verb::Post, "/users/:id", target)
+        .unwrap();
+    assert!(!router.remove(Verb::Get, "/posts", None));
+    assert!(!router.remove(Verb::Post, "/users/:id", None));
+    assert!(router.remove(Verb::Get, "/users/#other", None));
+    assert!(router.string_var_routes().is_empty());
+    assert!(router.find_route(Verb::Get, "/users/bob", None).is_none());
+    let (path, domain) = (format!("/users/:{}", "id"), String::from("example.com"));
+    assert!(router.remove(Verb::Post, &path, Some(&domain)));
+    assert!(router.get_by_handle(handle).is_none());
+    assert_eq!(router.routes().len(), 0);
+    assert!(!router.remove(Verb::Get, "/users/#name", None));
+
+    router
+        .route(None, Verb::Get, "/list?limit=:int=20", target)
+        .unwrap();
+    assert!(router.remove(Verb::Get, "/list", None));
+    router
+        .route(None, Verb::Get, "/list?limit=:int=20", target)
+        .unwrap();
+    router
+        .route(None, Verb::Get, "/list?page=:int", target)
+        .unwrap();
+    assert!(!router.remove(Verb::Get, "/list", None));
+    assert!(router.remove(Verb::Get, "/list?page=:int", None));
+    assert!(router.remove(Verb::Get, "/list", None));
+    assert_eq!(router.routes().len(), 0);
+
+    router.route(None, Verb::Get, "/a", target).unwrap();
+    router.route(None, Verb::Get, "/b/#c", target).unwrap();
+    router.clear();
+    assert_eq!(router.routes().len(), 0);
+    assert!(router.string_var_routes().is_empty());
+    router.route(None, Verb::Get, "/a", target).unwrap();
+}
+
+#[test]
+pub fn test_router_len() {
+    let mut router = Router::new();
+    assert_eq!(router.len(), 0);
+    assert!(router.is_empty());
+    router.route(None, Verb::Get, "/a", target).unwrap();
+    assert_eq!(router.len(), 1);
+    router.route(None, Verb::Post, "/a", target).unwrap();
+    router.route(None, Verb::Get, "/a/:id", target).unwrap();
+    router.route(None, Verb::Get, "/b/#name/c", target).unwrap();
+    assert_eq!(router.len(), 4);
+    assert!(router.route(None, Verb::Get, "/a/:other", target).is_err());
+    assert_eq!(router.len(), 4);
+    assert!(!router.is_empty());
+    router.remove(Verb::Get, "/a/:id", None);
+    assert_eq!(router.len(), 3);
+}
+
+#[test]
+pub fn test_mount() {
+    let mut users = Router::new();
+    users.route(None, Verb::Get, "/users", target).unwrap();
+    users.route(None, Verb::Get, "/users/:id", target).unwrap();
+    let mut router = Router::new();
+    router.route(None, Verb::Get, "/", target).unwrap();
+    router.mount("/api", users.clone()).unwrap();
+    assert_eq!(router.len(), 3);
+    assert!(router.find(Verb::Get, "/api/users/5", None).is_some());
+    assert!(router.find(Verb::Get, "/api/users", None).is_some());
+    assert!(router.find(Verb::Get, "/users/5", None).is_none());
+    let found = router.find_route(Verb::Get, "/api/users/5", None).unwrap();
+    assert_eq!(found.params().get_int("id"), Some(5));
+
+    assert_eq!(
+        router.mount("/api", users.clone()),
+        Err("a route identical to this one has already been defined!")
+    );
+    assert_eq!(router.len(), 3);
+    assert_eq!(
+        router.mount("/v/:version", users.clone()),
+        Err("mount prefix must be static")
+    );
+    assert_eq!(router.mount("api", users), Err("invalid route format!"));
+
+    // routes of the sub-router that overlap each other are caught before any is mounted
+    let mut people = Router::new();
+    people.route(None, Verb::Get, "/users/:id", target).unwrap();
+    people
+        .route(None, Verb::Get, "/users/#name", target)
+        .unwrap();
+    let mut router = Router::new();
+    router.set_reject_conflicts(true);
+    assert_eq!(
+        router.mount("/api", people),
+        Err("ambiguous route overlaps an existing route")
+    );
+    assert_eq!(router.len(), 0);
+}
+
+#[test]
+pub fn test_route_group() {
+    let mut router = Router::new();
+    router
+        .group("/admin")
+        .domain("example.com")
+        .get("/", target)
+        .unwrap()
+        .get("/users/:id", target)
+        .unwrap()
+        .post("/users", target)
+        .unwrap();
+    assert_eq!(router.len(), 3);
+    assert!(router
+        .find(Verb::Get, "/admin", Some("example.com"))
+        .is_some());
+    assert!(router
+        .find(Verb::Get, "/admin/users/4", Some("example.com"))
+        .is_some());
+    assert!(router
+        .find(Verb::Post, "/admin/users", Some("example.com"))
+        .is_some());
+    assert!(router
+        .find(Verb::Post, "/admin/users", Some("other.com"))
+        .is_none());
+    assert!(router
+        .find(Verb::Get, "/users/4", Some("example.com"))
+        .is_none());
+    assert!(router.group("/admin").get("/", target).is_ok());
+    assert!(matches!(
+        router.group("/admin/:id").get("/", target),
+        Err(RouteError::DynamicPrefix)
+    ));
+    assert!(matches!(
+        router.group("admin").get("/", target),
+        Err(RouteError::InvalidPathFormat)
+    ));
+
+    router
+        .group("/api")
+        .path("/items/:id")
+        .verbs(&[Verb::Get, Verb::Delete])
+        .name("item")
+        .route(target)
+        .unwrap();
+    let found = router
+        .find_route(Verb::Delete, "/api/items/4", None)
+        .unwrap();
+    assert_eq!(found.template(), "/api/items/:id");
+    assert_eq!(found.route().meta("name"), Some("item"));
+    assert!(router.find(Verb::Get, "/api/items/4", None).is_some());
+}
+
+#[test]
+pub fn test_url_for() {
+    let mut router = Router::new();
+    router
+        .path("/users/:id")
+        .name("user_show")
+        .route(target)
+        .unwrap();
+    router
+        .path("/files/#owner/*rest")
+        .name("file")
+        .route(target)
+        .unwrap();
+    router
+        .path("/tags/,tags/;score/$blob")
+        .name("tags")
+        .route(target)
+        .unwrap();
+    let mut params = UrlParams::new();
+    params.add("id", UrlParam::Int(5));
+    assert_eq!(
+        router.url_for("user_show", &params),
+        Some("/users/5".to_string())
+    );
+    assert_eq!(router.url_for("nope", &params), None);
+    let mut wrong = UrlParams::new();
+    wrong.add("id", UrlParam::String("5".to_string()));
+    assert_eq!(router.url_for("user_show", &wrong), None);
+    assert_eq!(router.url_for("user_show", &UrlParams::new()), None);
+
+    let mut params = UrlParams::new();
+    params.add("owner", UrlParam::String("jo smith".to_string()));
+    params.add("rest", UrlParam::String("docs/a&b.txt".to_string()));
+    let url = router.url_for("file", &params).unwrap();
+    assert_eq!(url, "/files/jo%20smith/docs/a%26b.txt");
+    let found = router.find_route(Verb::Get, &url, None).unwrap();
+    assert_eq!(found.params().get_str("owner"), Some("jo smith"));
+    assert_eq!(found.params().get_str("rest"), Some("docs/a&b.txt"));
+
+    let mut params = UrlParams::new();
+    params.add(
+        "tags",
+        UrlParam::Array(vec![
+            UrlParam::String("a".to_string()),
+            UrlParam::String("b,c".to_string()),
+        ]),
+    );
+    params.add("score", UrlParam::Float(2.0));
+    params.add("blob", UrlParam::Bytes(vec![0xfb, 0xff, 0x01]));
+    let url = router.url_for("tags", &params).unwrap();
+    assert_eq!(url, "/tags/a,b%2Cc/2.0/-_8B");
+    let found = router.find_route(Verb::Get, &url, None).unwrap();
+    assert_eq!(found.params().get_float("score"), Some(2.0));
+    assert_eq!(
+        found.params().get("blob"),
+        Some(&UrlParam::Bytes(vec![0xfb, 0xff, 0x01]))
+    );
+}
+
+#[test]
+pub fn test_head_and_any() {
+    let mut router = Router::new();
+    router.on("/ping").head().route(target).unwrap();
+    assert!(router.find(Verb::Head, "/ping", None).is_some());
+    assert!(router.find(Verb::Get, "/ping", None).is_none());
+
+    router.on("/anything/:id").tag("all").any(target).unwrap();
+    for verb in Verb::all() {
+        assert!(router.find(verb, "/anything/3", None).is_some());
+    }
+    assert_eq!(router.len(), 1 + Verb::all().len());
+    assert!(router
+        .routes()
+        .all(|route| route.to_path_string() == "/ping" || route.tags == vec!["all".to_string()]));
+
+    router.path("/taken").post().route(target).unwrap();
+    assert!(matches!(
+        router.on("/taken").any(target),
+        Err(RouteError::DuplicateRoute)
+    ));
+    assert!(router.find(Verb::Get, "/taken", None).is_none());
+}
+
+#[test]
+pub fn test_route_verbs() {
+    let mut router = Router::new();
+    router
+        .route_verbs(&[Verb::Get, Verb::Head], None, "/status", target)
+        .unwrap();
+    assert!(router.find(Verb::Get, "/status", None).is_some());
+    assert!(router.find(Verb::Head, "/status", None).is_some());
+    assert!(router.find(Verb::Post, "/status", None).is_none());
+
+    router
+        .route(None, Verb::Patch, "/items/:id", target)
+        .unwrap();
+    let before = router.to_snapshot();
+    assert_eq!(
+        router.route_verbs(
+            &[Verb::Put, Verb::Patch, Verb::Delete],
+            None,
+            "/items/:id",
+            target
+        ),
+        Err("a route identical to this one has already been defined!")
+    );
+    assert_eq!(router.to_snapshot(), before);
+    assert_eq!(router.len(), 3);
+
+    router
+        .on("/things")
+        .verbs(&[Verb::Put, Verb::Patch, Verb::Put])
+        .route(target)
+        .unwrap();
+    assert!(router.find(Verb::Put, "/things", None).is_some());
+    assert!(router.find(Verb::Patch, "/things", None).is_some());
+    assert_eq!(router.len(), 5);
+}
+
+#[test]
+pub fn test_find_resolves_dynamic_routes() {
+    let mut router = Router::new();
+    router
+        .route_dynamic(Verb::Get, "/landing", select_variant)
+        .unwrap();
+    let endpoint = router.find(Verb::Get, "/landing", None).unwrap();
+    assert_eq!(
+        plain_body(endpoint(
+            &UrlParams::new(),
+            &VerbParams::new(),
+            &VerbParams::new()
+        )),
+        "a"
+    );
+    let (endpoint, _) = router
+        .find_with_params(Verb::Get, "/landing?beta=1", None)
+        .unwrap();
+    assert_eq!(
+        plain_body(endpoint(
+            &UrlParams::new(),
+            &VerbParams::new(),
+            &VerbParams::new()
+        )),
+        "b"
+    );
+    let mut chain = RouterChain::new();
+    chain.push(router);
+    let endpoint = chain.find(Verb::Get, "/landing?beta=1", None).unwrap();
+    assert_eq!(endpoint as usize, variant_b as Endpoint as usize);
+}
+
+#[test]
+pub fn test_alias_specs_come_from_alias_path() {
+    let mut router = Router::new();
+    router
+        .route(None, Verb::Get, "/users/:id", echo_limit)
+        .unwrap();
+    router.alias("/u/:id?limit=:int=20", "/users/:id").unwrap();
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/u/5", None)),
+        "Some(Int(20))"
+    );
+    assert_eq!(
+        plain_body(router.dispatch(Verb::Get, "/users/5", None)),
+        "None"
+    );
+    router
+        .route(None, Verb::Get, "/posts/:page", target)
+        .unwrap();
+    router.alias("/p/:page?", "/posts/:page").unwrap();
+    assert!(router.find_route(Verb::Get, "/p", None).is_some());
+    assert!(router.find_route(Verb::Get, "/posts", None).is_none());
+}
+
+#[test]
+pub fn test_alias_is_atomic() {
+    let mut router = Router::new();
+    router.route(None, Verb::Get, "/users/:id", target).unwrap();
+    router
+        .route(None, Verb::Post, "/users/:id", target)
+        .unwrap();
+    router.route(None, Verb::Post, "/u/:id", target).unwrap();
+    assert_eq!(
+        router.alias("/u/:id", "/users/:id"),
+        Err(RouteError::DuplicateRoute)
+    );
+    assert!(router.find(Verb::Get, "/u/5", None).is_none());
+    assert_eq!(router.len(), 3);
+
+    // only the POST alias overlaps `/v/#name`, so the GET alias must not be left behind
+    router.set_reject_conflicts(true);
+    router.route(None, Verb::Post, "/v/#name", target).unwrap();
+    assert_eq!(
+        router.alias("/v/:id", "/users/:id"),
+        Err(RouteError::AmbiguousRoute)
+    );
+    assert!(router.find(Verb::Get, "/v/5", None).is_none());
+    assert_eq!(router.len(), 4);
+}
+
+struct Teapot(u16);
+
+impl HandlerError for Teapot {
+    fn status(&self) -> u16 {
+        self.0
+    }
+
+    fn message(&self) -> String {
+        "short and stout".to_string()
+    }
+}
+
+fn brew(
+    url: &UrlParams,
+    _get: &VerbParams,
+    _post: &VerbParams,
+) -> Result<Render, Box<dyn HandlerError>> {
+    Err(Box::new(Teapot(url.get_int("status").unwrap() as u16)))
+}
+
+#[test]
+pub fn test_fallible_routes() {
+    let mut router = Router::new();
+    router
+        .route_fallible(None, Verb::Get, "/signup", signup)
+        .unwrap();
+    router
+        .route_fallible(None, Verb::Get, "/brew/:status", brew)
+        .unwrap();
+    let m = router.find_route(Verb::Get, "/signup", None).unwrap();
+    let mut get = VerbParams::new();
+    get.add(
+        "email".to_string(),
+        VerbParam::String("a@b.com".to_string()),
+    );
+    assert!(matches!(m.call(&get, &VerbParams::new()), Render::Json(_)));
+    assert_eq!(
+        m.call(&VerbParams::new(), &VerbParams::new()).status_code(),
+        422
+    );
+
+    assert_eq!(
+        router.dispatch(Verb::Get, "/brew/418", None).status_code(),
+        418
+    );
+    assert_eq!(
+        router.dispatch(Verb::Get, "/brew/600", None).status_code(),
+        500
+    );
+    assert_eq!(
+        router.dispatch(Verb::Get, "/brew/99", None).status_code(),
+        500
+    );
+    router.set_json_errors(true);
+    assert_eq!(
+        router.dispatch(Verb::Get, "/brew/600", None).status_code(),
+        500
+    );
 }
@@ -0,0 +1,99 @@
+use crate::router::*;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+fn hello(url: &UrlParams, get: &VerbParams, _post: &VerbParams) -> Render {
+    Render::Plain(format!(
+        "hello {} from {}",
+        url.get_str("name").unwrap(),
+        get.get_str("from").unwrap_or("nowhere")
+    ))
+}
+
+fn rows(_url: &UrlParams, _get: &VerbParams, _post: &VerbParams) -> Render {
+    let chunks = vec![b"a,b\n".to_vec(), b"1,2\n".to_vec()];
+    Render::Stream("text/csv".to_string(), Box::new(chunks.into_iter()))
+}
+
+fn request_stream(port: u16) -> TcpStream {
+    // the server may still be binding, so retry briefly
+    (0..50)
+        .find_map(|_| {
+            TcpStream::connect(("127.0.0.1", port))
+                .map_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .unwrap()
+}
+
+fn request(port: u16, raw: &str) -> String {
+    let mut stream = request_stream(port);
+    stream.write_all(raw.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_serve() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    thread::spawn(move || {
+        let mut router = Router::new();
+        router
+            .route(Some("example.com"), Verb::Get, "/hello/#name", hello)
+            .unwrap();
+        router.route(None, Verb::Get, "/rows", rows).unwrap();
+        router
+            .path("/upload")
+            .verb(Verb::Post)
+            .max_body(4)
+            .route(hello)
+            .unwrap();
+        router.set_default_headers(vec![("X-Frame-Options".to_string(), "DENY".to_string())]);
+        router.serve(&format!("127.0.0.1:{}", port)).unwrap();
+    });
+    // a client that never sends its request doesn't hold up the others
+    let _idle = request_stream(port);
+    let response = request(
+        port,
+        "GET /hello/sam?from=tests HTTP/1.1\r\nHost: example.com:8080\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nhello sam from tests"));
+    let response = request(port, "GET /hello/sam HTTP/1.1\r\nHost: other.com\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    let response = request(port, "GET /rows HTTP/1.1\r\n\r\n");
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(response.ends_with("\r\n\r\n4\r\na,b\n\r\n4\r\n1,2\n\r\n0\r\n\r\n"));
+    // answered from the declared length alone, without waiting on a body that never comes
+    let response = request(
+        port,
+        "POST /upload HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    let response = request(
+        port,
+        &format!(
+            "GET /rows HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            DEFAULT_MAX_BODY + 1
+        ),
+    );
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    // the oversized line is the last thing sent, so the server reads all of it
+    let request_line = "GET /rows HTTP/1.1\r\n";
+    let header = format!(
+        "X-Big: {}",
+        "a".repeat(16 * 1024 + 1 - request_line.len() - 7)
+    );
+    let response = request(port, &format!("{}{}", request_line, header));
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    let response = request(port, "BREW /pot HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+    assert!(response.contains("X-Frame-Options: DENY\r\n"));
+}